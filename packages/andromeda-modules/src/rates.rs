@@ -1,20 +1,137 @@
 use andromeda_std::{
-    amp::recipient::Recipient, andr_exec, andr_instantiate, andr_query, error::ContractError,
+    amp::recipient::Recipient, andr_exec, andr_instantiate, andr_query, common::Funds,
+    error::ContractError,
 };
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{ensure, Coin, Decimal, Fraction, QuerierWrapper};
+use cosmwasm_std::{
+    ensure, from_binary, Binary, BlockInfo, Coin, Decimal, Fraction, QuerierWrapper, StdError,
+    Uint128, Uint256,
+};
 
 #[andr_instantiate]
 #[cw_serde]
 pub struct InstantiateMsg {
     pub rates: Vec<RateInfo>,
+    /// Flat fees paid by the transferrer on NFT transfers made outside a sale
+    pub transfer_rates: Option<Vec<RateInfo>>,
 }
 
 #[andr_exec]
 #[cw_serde]
 pub enum ExecuteMsg {
-    UpdateRates { rates: Vec<RateInfo> },
-    UpdateSaleTimestamp { last_timestamp: u64 },
+    UpdateRates {
+        rates: Vec<RateInfo>,
+    },
+    /// Replaces the rate with the given description, can be called by its managers
+    UpdateRate {
+        description: String,
+        rate: RateInfo,
+    },
+    /// Sets the roles of an address, `None` revokes them
+    UpdateRoles {
+        address: String,
+        roles: Option<Roles>,
+    },
+    UpdateTransferRates {
+        rates: Vec<RateInfo>,
+    },
+    /// Restricts the assets payments can be made in, `None` accepts every asset
+    UpdateAcceptedAssets {
+        accepted_assets: Option<AcceptedAssets>,
+    },
+    /// Caps the total share of a payment royalties can deduct, `None` removes the cap. Inclusive
+    /// taxes are deducted in full on top of the capped royalties.
+    UpdateDeductionCap {
+        cap: Option<Decimal>,
+    },
+    /// Moves the shared sale clock, the last sale height is left unchanged if not set. Only the
+    /// owner or an operator can move it, apart from registered hook callers reporting the
+    /// `payment` of a sale, which can only move it to where the reset policy moves it. The clock
+    /// can't be set past the current block and every other move is recorded in the audit log.
+    UpdateSaleTimestamp {
+        last_timestamp: u64,
+        last_height: Option<u64>,
+        payment: Option<Funds>,
+    },
+    /// Moves the sale clock of a rate with its own clock, in the same way as the shared clock
+    UpdateRateTimestamp {
+        description: String,
+        last_timestamp: u64,
+        last_height: Option<u64>,
+        payment: Option<Funds>,
+    },
+    /// Sets the approvers rate changes have to be approved by. Only the owner can set the first
+    /// approvers, any later change has to be proposed to the current approvers. Setting the first
+    /// approvers revokes every managed rate, managers have to be appointed again by proposal.
+    UpdateApprovers {
+        approver_set: Option<ApproverSet>,
+    },
+    /// Proposes a change to be approved by the approvers, counting the proposer's approval
+    Propose {
+        action: ProposalAction,
+        /// The block time in seconds after which the proposal can no longer be approved
+        expiration: u64,
+    },
+    /// Approves a proposal, applying its change once the threshold is met
+    Approve {
+        proposal_id: u64,
+    },
+    /// Replaces the sender's own entry in the recipients of a rate. While a veto period is set
+    /// the rotation is pending until the period ends, during which the owner can veto it.
+    RotateRecipient {
+        description: String,
+        recipient: Recipient,
+    },
+    /// Applies a pending rotation once its veto period has ended, can be called by anyone
+    ApplyRotation {
+        rotation_id: u64,
+    },
+    VetoRotation {
+        rotation_id: u64,
+    },
+    /// Sets how long in seconds the owner can veto recipient rotations, `None` applies them
+    /// immediately
+    UpdateRotationVetoPeriod {
+        period: Option<u64>,
+    },
+    /// Commits to a rate, restricting how it can be changed while the commitment is active. An
+    /// active commitment can't be replaced.
+    CommitRate {
+        commitment: RateCommitment,
+    },
+    /// Pauses fee collection, can be called by the owner or an operator
+    Pause {
+        mode: PauseMode,
+        /// The block time in seconds at which fee collection resumes, paused until unpaused if unset
+        until: Option<u64>,
+    },
+    Unpause {},
+    /// Sets the limits on the fees charged within a rolling window, `None` removes them
+    UpdateCircuitBreaker {
+        circuit_breaker: Option<CircuitBreaker>,
+    },
+    /// Clears the tracked fees and resumes fee collection after a trip, can be called by the
+    /// owner or an operator
+    ResetCircuitBreaker {},
+    /// Sets the addresses allowed to report sales, the marketplaces calling the funds transfer hook.
+    /// The hook only reports sales once callers are registered, until then the circuit breaker,
+    /// the dynamic rates and the sale clocks don't track sales.
+    UpdateHookCallers {
+        callers: Vec<String>,
+    },
+    /// Adds the fees charged on a sale to the circuit breaker's window, sent by the funds transfer
+    /// hook on behalf of a registered hook caller
+    RecordFees {
+        payment: Funds,
+        fees: Uint128,
+    },
+    /// Sets how sales move the sale clock, `None` moves it to the time of every sale
+    UpdateClockResetPolicy {
+        policy: Option<ClockResetPolicy>,
+    },
+    /// Adjusts the dynamic rates to the time since the last sale, sent by the funds transfer hook
+    /// on behalf of a registered hook caller
+    RecordSale {},
 }
 
 #[cw_serde]
@@ -26,12 +143,852 @@ pub struct MigrateMsg {}
 pub enum QueryMsg {
     #[returns(PaymentsResponse)]
     Payments {},
+    #[returns(TransferRatesResponse)]
+    TransferRates {},
+    #[returns(AcceptedAssetsResponse)]
+    AcceptedAssets {},
+    #[returns(DeductionCapResponse)]
+    DeductionCap {},
+    /// The rates in effect at the current block time
+    #[returns(CurrentRatesResponse)]
+    CurrentRates {
+        start_after: Option<u32>,
+        limit: Option<u32>,
+    },
+    /// The rates in effect at the given time in seconds or block height, exactly one has to be set
+    #[returns(RatesAtResponse)]
+    RatesAt {
+        time: Option<u64>,
+        height: Option<u64>,
+    },
+    /// Every saved version of the rates, paginated by version
+    #[returns(RateHistoryResponse)]
+    RateHistory {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// The administrative changes made to the contract, paginated by entry id
+    #[returns(AuditLogResponse)]
+    AuditLog {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(PauseStatusResponse)]
+    PauseStatus {},
+    #[returns(CircuitBreakerResponse)]
+    CircuitBreaker {},
+    #[returns(HookCallersResponse)]
+    HookCallers {},
+    #[returns(ClockResetPolicyResponse)]
+    ClockResetPolicy {},
+    /// The sale clock of the rate with the given description, the shared clock if it has none of
+    /// its own
+    #[returns(SaleClockResponse)]
+    SaleClock { description: String },
+    /// The current value of the dynamic rate with the given description
+    #[returns(DynamicFeeState)]
+    DynamicRate { description: String },
+    #[returns(RateCommitment)]
+    Commitment { description: String },
+    /// The rate commitments, paginated by rate description
+    #[returns(CommitmentsResponse)]
+    Commitments {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(RotationVetoPeriodResponse)]
+    RotationVetoPeriod {},
+    /// The recipient rotations waiting for their veto period to end, paginated by rotation id
+    #[returns(PendingRotationsResponse)]
+    PendingRotations {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(RolesResponse)]
+    Roles { address: String },
+    /// The addresses holding roles, paginated by address
+    #[returns(RoleHoldersResponse)]
+    RoleHolders {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(ApproversResponse)]
+    Approvers {},
+    #[returns(Proposal)]
+    Proposal { proposal_id: u64 },
+    /// The proposed changes along with their approvals, paginated by proposal id
+    #[returns(ProposalsResponse)]
+    Proposals {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// The rates paying the given recipient address or AMP path
+    #[returns(RatesForRecipientResponse)]
+    RatesForRecipient { address: String },
+    /// The configured rates along with their index, filtered and paginated by index
+    #[returns(RatesResponse)]
+    Rates {
+        start_after: Option<u32>,
+        limit: Option<u32>,
+        filter: Option<RateFilter>,
+    },
+    /// What the seller nets when selling at `amount`
+    #[returns(PricingResponse)]
+    NetProceeds {
+        amount: Funds,
+        /// The sale the rates are evaluated for, sale-conditioned rates are left out if not set
+        context: Option<SaleContext>,
+    },
+    /// The price the buyer has to pay for the seller to net `net`
+    #[returns(PricingResponse)]
+    RequiredPayment {
+        net: Funds,
+        context: Option<SaleContext>,
+    },
+    /// What the buyer pays in total, including additive taxes, when buying at `amount`
+    #[returns(PricingResponse)]
+    TotalBuyerCost {
+        amount: Funds,
+        context: Option<SaleContext>,
+    },
 }
 
 #[cw_serde]
 pub struct PaymentsResponse {
     pub payments: Vec<RateInfo>,
     pub last_timestamp: u64,
+    pub last_height: u64,
+}
+
+#[cw_serde]
+pub struct TransferRatesResponse {
+    pub rates: Vec<RateInfo>,
+}
+
+#[cw_serde]
+pub struct AcceptedAssetsResponse {
+    pub accepted_assets: Option<AcceptedAssets>,
+}
+
+#[cw_serde]
+/// The breakdown of a sale price after the rates are applied at the current block time
+pub struct PricingResponse {
+    /// The sale price
+    pub price: Funds,
+    /// What the seller nets after deducted rates
+    pub net: Funds,
+    /// What the buyer pays including additive taxes
+    pub total_buyer_cost: Funds,
+}
+
+#[cw_serde]
+pub struct RatesResponse {
+    pub rates: Vec<IndexedRate>,
+}
+
+#[cw_serde]
+/// A snapshot of the rates taken whenever they are changed
+pub struct RateVersion {
+    pub version: u64,
+    /// The block height at which the rates were saved
+    pub height: u64,
+    /// The block time in seconds at which the rates were saved
+    pub time: u64,
+    pub rates: Vec<RateInfo>,
+}
+
+#[cw_serde]
+pub struct RatesAtResponse {
+    /// The version in effect, none if the rates had not been set yet
+    pub version: Option<RateVersion>,
+}
+
+#[cw_serde]
+pub struct RateHistoryResponse {
+    pub versions: Vec<RateVersion>,
+}
+
+#[cw_serde]
+/// An administrative change recorded in the audit log
+pub struct AuditEntry {
+    pub id: u64,
+    /// The address that sent the change
+    pub actor: String,
+    /// The origin of the AMP packet the change was sent through, if any
+    pub origin: Option<String>,
+    /// The block time in seconds
+    pub time: u64,
+    pub action: String,
+    pub changes: Vec<AuditChange>,
+}
+
+#[cw_serde]
+/// A changed value, JSON encoded and none if it was unset
+pub struct AuditChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[cw_serde]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditEntry>,
+}
+
+#[cw_serde]
+pub enum PauseMode {
+    /// No fees are charged, the whole payment is left over
+    SkipFees,
+    /// The funds transfer hook returns an error so that sales fail, transfers go through without
+    /// fees
+    HaltSales,
+}
+
+#[cw_serde]
+pub struct Pause {
+    pub mode: PauseMode,
+    /// The block time in seconds at which fee collection resumes
+    pub until: Option<u64>,
+}
+
+impl Pause {
+    pub fn is_active(&self, current_timestamp: u64) -> bool {
+        self.until.map_or(true, |until| current_timestamp < until)
+    }
+}
+
+#[cw_serde]
+pub struct PauseStatusResponse {
+    /// The active pause, none if fee collection isn't paused
+    pub pause: Option<Pause>,
+}
+
+#[cw_serde]
+/// How a sale moves the sale clock the threshold decay is measured from. The first sale always
+/// starts the clock.
+pub enum ClockResetPolicy {
+    /// Only payments of at least the given amounts reset the clock, payments in other assets
+    /// don't move it
+    MinAmount { min_amounts: Vec<Coin> },
+    /// Payments move the clock towards the time of the sale in proportion to their amount,
+    /// reaching it at the given amounts. Payments in other assets don't move it.
+    Proportional { full_reset_amounts: Vec<Coin> },
+    /// Payments move the clock forward by at most `max_recovery` seconds, or blocks for thresholds
+    /// measured in blocks, so that the fee recovers over several sales
+    Gradual { max_recovery: u64 },
+}
+
+impl ClockResetPolicy {
+    /// Returns the sale clock after a payment, none if it doesn't move.
+    pub fn next_timestamp(
+        &self,
+        payment: &Coin,
+        current_timestamp: u64,
+        last_timestamp: u64,
+    ) -> Option<u64> {
+        if last_timestamp == 0 {
+            return Some(current_timestamp);
+        }
+        let elapsed = current_timestamp.saturating_sub(last_timestamp);
+        let find_amount = |amounts: &[Coin]| {
+            amounts
+                .iter()
+                .find(|amount| amount.denom == payment.denom)
+                .map(|amount| amount.amount)
+        };
+        let next_timestamp = match self {
+            ClockResetPolicy::MinAmount { min_amounts } => match find_amount(min_amounts) {
+                Some(min_amount) if payment.amount >= min_amount => current_timestamp,
+                _ => last_timestamp,
+            },
+            ClockResetPolicy::Proportional { full_reset_amounts } => {
+                match find_amount(full_reset_amounts) {
+                    Some(full_reset_amount) if !full_reset_amount.is_zero() => {
+                        let recovered = Uint128::from(elapsed).multiply_ratio(
+                            payment.amount.min(full_reset_amount),
+                            full_reset_amount,
+                        );
+                        last_timestamp + recovered.u128() as u64
+                    }
+                    _ => last_timestamp,
+                }
+            }
+            ClockResetPolicy::Gradual { max_recovery } => {
+                last_timestamp + elapsed.min(*max_recovery)
+            }
+        };
+        if next_timestamp == last_timestamp {
+            None
+        } else {
+            Some(next_timestamp)
+        }
+    }
+}
+
+#[cw_serde]
+pub struct ClockResetPolicyResponse {
+    pub policy: Option<ClockResetPolicy>,
+}
+
+#[cw_serde]
+/// A sale clock kept for a single thresholded rate instead of the clock shared by the rates. It
+/// starts with the first sale after the rate is added.
+pub struct RateClock {
+    /// How sales move the rate's clock, every sale resets it if unset
+    pub reset_policy: Option<ClockResetPolicy>,
+}
+
+#[cw_serde]
+#[derive(Default)]
+/// The time and block height of the last sale that moved a sale clock, zero before the first sale
+pub struct SaleClock {
+    pub last_timestamp: u64,
+    pub last_height: u64,
+}
+
+#[cw_serde]
+pub struct SaleClockResponse {
+    /// The block time in seconds the rate's threshold decay is measured from, zero before the
+    /// first sale
+    pub last_timestamp: u64,
+    /// The block height the rate's threshold decay is measured from, zero before the first sale
+    pub last_height: u64,
+    /// Whether the rate has a clock of its own
+    pub is_own_clock: bool,
+    pub reset_policy: Option<ClockResetPolicy>,
+}
+
+#[cw_serde]
+/// Adjusts a percentage rate after every sale, raising it when sales arrive faster than the target
+/// interval and lowering it when they arrive slower. The configured percentage is the starting
+/// value.
+pub struct DynamicFee {
+    /// The targeted time between sales in seconds
+    pub target_interval: u64,
+    /// The largest change of the rate on a single sale, as a share of the current rate
+    pub max_change: Decimal,
+    /// The lowest value of the rate, above zero as the rate is adjusted in proportion to itself
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+impl DynamicFee {
+    /// Returns the state of a dynamic rate before the first sale.
+    pub fn initial_state(&self, percent: Decimal) -> DynamicFeeState {
+        DynamicFeeState {
+            percent: percent.max(self.min).min(self.max),
+            average_interval: self.target_interval,
+            last_sale: 0,
+        }
+    }
+
+    /// Returns the state of a dynamic rate after a sale at `current_timestamp`.
+    ///
+    /// The rate moves by `max_change` scaled by how far the moving average of the intervals
+    /// between sales is from the target, each interval weighing an eighth of the average.
+    pub fn record_sale(&self, state: &DynamicFeeState, current_timestamp: u64) -> DynamicFeeState {
+        // The first sale only starts measuring the intervals
+        if state.last_sale == 0 {
+            return DynamicFeeState {
+                last_sale: current_timestamp,
+                ..state.clone()
+            };
+        }
+        let interval = current_timestamp.saturating_sub(state.last_sale);
+        let average_interval = state
+            .average_interval
+            .saturating_mul(7)
+            .saturating_add(interval)
+            / 8;
+        let target = self.target_interval.max(1);
+        let percent = if average_interval < target {
+            let deviation = Decimal::from_ratio(target - average_interval, target);
+            state.percent + state.percent * self.max_change * deviation
+        } else {
+            let deviation =
+                Decimal::from_ratio(average_interval - target, target).min(Decimal::one());
+            state.percent - state.percent * self.max_change * deviation
+        };
+        DynamicFeeState {
+            percent: percent.max(self.min).min(self.max),
+            average_interval,
+            last_sale: current_timestamp,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct DynamicFeeState {
+    /// The percentage currently charged
+    pub percent: Decimal,
+    /// The moving average of the time between sales in seconds
+    pub average_interval: u64,
+    /// The block time in seconds of the last sale, zero before the first sale
+    pub last_sale: u64,
+}
+
+/// The number of buckets the circuit breaker's window is tracked in
+const BREAKER_BUCKETS: u64 = 10;
+
+#[cw_serde]
+/// Limits on the fees charged within a rolling window. Fee collection switches to `mode` once a
+/// limit is exceeded, until the circuit breaker is reset. The sale exceeding the limit is charged
+/// no fees in either mode, so that it goes through and records the trip.
+pub struct CircuitBreaker {
+    /// The length of the window in seconds
+    pub window: u64,
+    /// The most fees that can be charged per asset within the window, the denom being the cw20
+    /// address for cw20 assets
+    pub max_fees: Vec<Coin>,
+    /// The largest share of the volume within the window that can be charged as fees
+    pub max_volume_share: Option<Decimal>,
+    /// The largest share of a single payment that can be charged as fees
+    pub max_sale_ratio: Option<Decimal>,
+    pub mode: PauseMode,
+}
+
+impl CircuitBreaker {
+    fn bucket_length(&self) -> u64 {
+        (self.window / BREAKER_BUCKETS).max(1)
+    }
+
+    /// The index of the oldest bucket still in the window
+    fn oldest_bucket(&self, current_timestamp: u64) -> u64 {
+        current_timestamp.saturating_sub(self.window) / self.bucket_length()
+    }
+
+    /// Returns the volume and fees of an asset within the window.
+    pub fn window_totals(
+        &self,
+        state: &BreakerState,
+        current_timestamp: u64,
+        asset: &str,
+    ) -> (Uint128, Uint128) {
+        let oldest = self.oldest_bucket(current_timestamp);
+        state
+            .buckets
+            .iter()
+            .filter(|bucket| bucket.index >= oldest)
+            .flat_map(|bucket| bucket.totals.iter())
+            .filter(|totals| totals.asset == asset)
+            .fold(
+                (Uint128::zero(), Uint128::zero()),
+                |(volume, fees), totals| (volume + totals.volume, fees + totals.fees),
+            )
+    }
+
+    /// Returns which limit charging `fees` on `payment` exceeds, none if it is within the limits.
+    pub fn violation(
+        &self,
+        state: &BreakerState,
+        current_timestamp: u64,
+        payment: &Coin,
+        fees: Uint128,
+    ) -> Option<String> {
+        if let Some(max_sale_ratio) = self.max_sale_ratio {
+            if !payment.amount.is_zero()
+                && Decimal::from_ratio(fees, payment.amount) > max_sale_ratio
+            {
+                return Some(format!("sale fees exceed {max_sale_ratio} of the payment"));
+            }
+        }
+
+        let (volume, window_fees) = self.window_totals(state, current_timestamp, &payment.denom);
+        let (volume, window_fees) = (volume + payment.amount, window_fees + fees);
+        if let Some(max_fees) = self
+            .max_fees
+            .iter()
+            .find(|max_fees| max_fees.denom == payment.denom)
+        {
+            if window_fees > max_fees.amount {
+                return Some(format!("fees of {window_fees} exceed {max_fees}"));
+            }
+        }
+        if let Some(max_volume_share) = self.max_volume_share {
+            if !volume.is_zero() && Decimal::from_ratio(window_fees, volume) > max_volume_share {
+                return Some(format!("fees exceed {max_volume_share} of the volume"));
+            }
+        }
+        None
+    }
+
+    /// Adds a sale to the window, dropping the buckets that have left it.
+    pub fn record(
+        &self,
+        state: &mut BreakerState,
+        current_timestamp: u64,
+        payment: &Coin,
+        fees: Uint128,
+    ) {
+        let oldest = self.oldest_bucket(current_timestamp);
+        state.buckets.retain(|bucket| bucket.index >= oldest);
+
+        let index = current_timestamp / self.bucket_length();
+        if state
+            .buckets
+            .last()
+            .map_or(true, |bucket| bucket.index != index)
+        {
+            state.buckets.push(FeeBucket {
+                index,
+                totals: vec![],
+            });
+        }
+        if let Some(bucket) = state.buckets.last_mut() {
+            match bucket
+                .totals
+                .iter_mut()
+                .find(|totals| totals.asset == payment.denom)
+            {
+                Some(totals) => {
+                    totals.volume += payment.amount;
+                    totals.fees += fees;
+                }
+                None => bucket.totals.push(AssetTotals {
+                    asset: payment.denom.clone(),
+                    volume: payment.amount,
+                    fees,
+                }),
+            }
+        }
+    }
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct BreakerState {
+    /// The tracked volumes and fees, oldest first
+    pub buckets: Vec<FeeBucket>,
+    pub trip: Option<BreakerTrip>,
+}
+
+#[cw_serde]
+pub struct FeeBucket {
+    /// The block time divided by the bucket length
+    pub index: u64,
+    pub totals: Vec<AssetTotals>,
+}
+
+#[cw_serde]
+pub struct AssetTotals {
+    pub asset: String,
+    pub volume: Uint128,
+    pub fees: Uint128,
+}
+
+#[cw_serde]
+pub struct BreakerTrip {
+    /// The block time in seconds of the trip
+    pub time: u64,
+    /// The limit that was exceeded
+    pub reason: String,
+}
+
+#[cw_serde]
+pub struct CircuitBreakerResponse {
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub state: BreakerState,
+}
+
+#[cw_serde]
+pub struct HookCallersResponse {
+    pub callers: Vec<String>,
+}
+
+#[cw_serde]
+/// A guarantee to the recipients of a rate. While it is active the rate can't be removed, its
+/// value can't drop below `floor`, none of its recipients can be removed and its other settings
+/// can only change in ways that can't lower the fee.
+pub struct RateCommitment {
+    /// The description of the committed rate
+    pub description: String,
+    /// The lowest value the rate can be changed to, of the same kind and asset as the rate
+    pub floor: Option<Rate>,
+    /// The block time in seconds at which the commitment ends, permanent if unset
+    pub expiration: Option<u64>,
+    /// Prevents any change to the rate
+    pub immutable: bool,
+}
+
+impl RateCommitment {
+    pub fn is_active(&self, current_timestamp: u64) -> bool {
+        self.expiration
+            .map_or(true, |expiration| current_timestamp < expiration)
+    }
+
+    /// Returns why changing the rate from `old` to `new`, none if it is removed, breaks the
+    /// commitment.
+    pub fn violation(&self, old: &RateInfo, new: Option<&RateInfo>) -> Option<&'static str> {
+        let new = match new {
+            Some(new) => new,
+            None => return Some("the rate can't be removed"),
+        };
+        if self.immutable && old != new {
+            return Some("the rate is immutable");
+        }
+        if let Some(floor) = &self.floor {
+            if !new.lowest_rate().is_at_least(floor) {
+                return Some("the rate can't drop below its floor");
+            }
+        }
+        if old
+            .recipients
+            .iter()
+            .any(|recipient| !new.recipients.contains(recipient))
+        {
+            return Some("its recipients can't be removed");
+        }
+        // Dropping a condition or predicate only applies the rate to more sales, any other change
+        // to how the fee is calculated could lower it
+        let unchanged = RateInfo {
+            rate: old.rate.clone(),
+            recipients: old.recipients.clone(),
+            condition: new.condition.clone().or_else(|| old.condition.clone()),
+            predicate: new.predicate.clone().or_else(|| old.predicate.clone()),
+            ..new.clone()
+        };
+        if &unchanged != old {
+            return Some("only its rate and recipients can be changed");
+        }
+        None
+    }
+}
+
+#[cw_serde]
+pub struct CommitmentsResponse {
+    pub commitments: Vec<RateCommitment>,
+}
+
+#[cw_serde]
+/// A recipient rotation waiting for its veto period to end
+pub struct PendingRotation {
+    pub id: u64,
+    /// The description of the rate the recipient is paid by
+    pub description: String,
+    pub old_recipient: Recipient,
+    pub new_recipient: Recipient,
+    /// The block time in seconds from which the rotation can be applied
+    pub executable_at: u64,
+}
+
+#[cw_serde]
+pub struct RotationVetoPeriodResponse {
+    pub period: Option<u64>,
+}
+
+#[cw_serde]
+pub struct PendingRotationsResponse {
+    pub rotations: Vec<PendingRotation>,
+}
+
+#[cw_serde]
+/// Roles delegating part of the owner's permissions
+pub struct Roles {
+    /// Operators can reset the sale clock but can't change rates
+    pub operator: bool,
+    /// The descriptions of the rates the address can change
+    pub managed_rates: Vec<String>,
+}
+
+#[cw_serde]
+pub struct RolesResponse {
+    pub roles: Option<Roles>,
+}
+
+#[cw_serde]
+pub struct RoleHolder {
+    pub address: String,
+    pub roles: Roles,
+}
+
+#[cw_serde]
+pub struct RoleHoldersResponse {
+    pub holders: Vec<RoleHolder>,
+}
+
+#[cw_serde]
+/// The approvers of which `threshold` have to approve a change
+pub struct ApproverSet {
+    pub approvers: Vec<String>,
+    pub threshold: u32,
+}
+
+#[cw_serde]
+pub enum ProposalAction {
+    UpdateRates {
+        rates: Vec<RateInfo>,
+    },
+    UpdateApprovers {
+        approver_set: Option<ApproverSet>,
+    },
+    UpdateRoles {
+        address: String,
+        roles: Option<Roles>,
+    },
+    UpdateTransferRates {
+        rates: Vec<RateInfo>,
+    },
+    UpdateDeductionCap {
+        cap: Option<Decimal>,
+    },
+}
+
+#[cw_serde]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: String,
+    pub action: ProposalAction,
+    pub approvals: Vec<String>,
+    /// The block time in seconds after which the proposal can no longer be approved
+    pub expiration: u64,
+    pub executed: bool,
+}
+
+#[cw_serde]
+pub struct ApproversResponse {
+    pub approver_set: Option<ApproverSet>,
+}
+
+#[cw_serde]
+pub struct ProposalsResponse {
+    pub proposals: Vec<Proposal>,
+}
+
+#[cw_serde]
+pub struct RatesForRecipientResponse {
+    pub rates: Vec<RecipientRate>,
+}
+
+#[cw_serde]
+/// A rate paying a recipient
+pub struct RecipientRate {
+    /// The index of the rate in the configured rates
+    pub index: u32,
+    pub description: Option<String>,
+    pub is_additive: bool,
+    pub rate: Rate,
+    /// The share of the fee paid to the recipient, the full fee if not set
+    pub share: Option<Decimal>,
+    /// Whether the recipient is paid the dust left after splitting the fee rather than a share
+    pub is_dust_recipient: bool,
+}
+
+impl RecipientRate {
+    pub fn new(
+        index: u32,
+        rate_info: &RateInfo,
+        share: Option<Decimal>,
+        is_dust_recipient: bool,
+    ) -> RecipientRate {
+        RecipientRate {
+            index,
+            description: rate_info.description.clone(),
+            is_additive: rate_info.is_additive,
+            rate: rate_info.rate.clone(),
+            share,
+            is_dust_recipient,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct IndexedRate {
+    /// The index of the rate in the configured rates
+    pub index: u32,
+    pub rate: RateInfo,
+}
+
+#[cw_serde]
+/// A filter on the configured rates, every field that is set must match
+pub struct RateFilter {
+    /// Only additive (`true`) or deducted (`false`) rates
+    pub is_additive: Option<bool>,
+    /// Only rates that can apply to payments in the given native denom or cw20 address
+    pub asset: Option<String>,
+    /// Only rates paying the given recipient address
+    pub recipient: Option<String>,
+}
+
+impl RateFilter {
+    pub fn matches(&self, rate_info: &RateInfo) -> bool {
+        if matches!(self.is_additive, Some(is_additive) if is_additive != rate_info.is_additive) {
+            return false;
+        }
+        if let Some(asset) = &self.asset {
+            let applies_to_asset = match rate_info.rate.flat_asset() {
+                // Flat rates are paid in their own asset
+                Some((flat_asset, _)) => flat_asset == *asset,
+                // Percentage rates are paid in the asset of the payment
+                None => rate_info.predicate.as_ref().map_or(true, |predicate| {
+                    predicate
+                        .allowed_assets
+                        .as_ref()
+                        .map_or(true, |assets| assets.contains(asset))
+                        && !predicate
+                            .denied_assets
+                            .as_ref()
+                            .map_or(false, |assets| assets.contains(asset))
+                }),
+            };
+            if !applies_to_asset {
+                return false;
+            }
+        }
+        if let Some(recipient) = &self.recipient {
+            let dust_recipient = rate_info
+                .rounding
+                .as_ref()
+                .and_then(|rounding| rounding.dust_recipient.as_ref());
+            return rate_info
+                .recipients
+                .iter()
+                .chain(dust_recipient)
+                .any(|receiver| receiver.get_addr() == *recipient);
+        }
+        true
+    }
+}
+
+#[cw_serde]
+pub struct CurrentRatesResponse {
+    pub rates: Vec<CurrentRate>,
+}
+
+#[cw_serde]
+pub struct CurrentRate {
+    /// The index of the rate in the configured rates
+    pub index: u32,
+    pub description: Option<String>,
+    pub is_additive: bool,
+    /// The flat amount or percentage in effect after the threshold decay
+    pub rate: Rate,
+    /// The block time in seconds, or the block height for thresholds measured in blocks, at which
+    /// the rate next changes if it is still decaying
+    pub next_change: Option<u64>,
+}
+
+#[cw_serde]
+pub struct DeductionCapResponse {
+    pub cap: Option<Decimal>,
+}
+
+#[cw_serde]
+/// The assets that payments processed by the rates contract can be made in
+pub struct AcceptedAssets {
+    pub native_denoms: Vec<String>,
+    pub cw20_addresses: Vec<String>,
+}
+
+impl AcceptedAssets {
+    /// Returns whether a payment in the given native denom or cw20 address is accepted.
+    pub fn accepts(&self, asset: &str, is_native: bool) -> bool {
+        let assets = if is_native {
+            &self.native_denoms
+        } else {
+            &self.cw20_addresses
+        };
+        assets.iter().any(|accepted| accepted == asset)
+    }
 }
 
 #[cw_serde]
@@ -43,13 +1000,360 @@ pub struct RateInfo {
 
     // Threshold will be applied for fiat rate only for now
     pub threshold: Option<Thredshold>,
+    /// Optional condition on the sale context, the rate is only applied if it matches
+    pub condition: Option<SaleCondition>,
+    /// Optional predicates on the payment, the rate is skipped if any of them fails
+    pub predicate: Option<RatePredicate>,
+    /// Optional rounding of the fee, percentage fees are rounded up if not set
+    pub rounding: Option<Rounding>,
+    /// Optional shares of the fee for each recipient, in the same order as `recipients`. If not
+    /// set every recipient is paid the full fee.
+    pub shares: Option<Vec<Decimal>>,
+    /// Optional base amount percentage fees are calculated on, the gross payment if not set
+    pub base: Option<RateBase>,
+    /// Whether an additive percentage rate is included in the payment rather than added on top
+    /// of it, in which case the tax is carved out of the payment
+    pub inclusive: Option<bool>,
+    /// Optional adjustment of a percentage rate to the pace of sales, requires a description
+    pub dynamic: Option<DynamicFee>,
+    /// Optional sale clock of the rate's own, requires a description and a threshold. The rate
+    /// decays from the shared sale clock if not set.
+    pub clock: Option<RateClock>,
+}
+
+impl RateInfo {
+    /// Returns whether the rate applies to a sale with the given context.
+    ///
+    /// Unconditioned rates always apply. Conditioned rates never apply when the sale context is
+    /// missing.
+    pub fn applies_to(&self, context: &Option<SaleContext>) -> bool {
+        match (&self.condition, context) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(condition), Some(context)) => condition.matches(context),
+        }
+    }
+
+    /// Returns the lowest rate that can be charged, as flat fees decay down to their threshold
+    /// value and dynamic rates are adjusted down to their minimum.
+    pub fn lowest_rate(&self) -> Rate {
+        match (&self.rate, &self.threshold, &self.dynamic) {
+            (Rate::Flat(coin), Some(threshold), _) => Rate::Flat(Coin::new(
+                coin.amount.u128().min(threshold.value),
+                coin.denom.clone(),
+            )),
+            (Rate::Cw20Flat(rate), Some(threshold), _) => Rate::Cw20Flat(Cw20FlatRate {
+                address: rate.address.clone(),
+                amount: rate.amount.min(Uint128::new(threshold.value)),
+            }),
+            (Rate::Percent(_), _, Some(dynamic)) => Rate::Percent(PercentRate {
+                percent: dynamic.min,
+            }),
+            _ => self.rate.clone(),
+        }
+    }
+
+    /// Returns the unit the rate's threshold decays in, seconds for rates without a threshold.
+    pub fn clock_unit(&self) -> ClockUnit {
+        self.threshold
+            .as_ref()
+            .and_then(|threshold| threshold.duration_unit.clone())
+            .unwrap_or(ClockUnit::Seconds)
+    }
+
+    /// Returns the current block time or height, in the unit the rate's threshold decays in, along
+    /// with the last sale on `clock` in the same unit.
+    pub fn clock_position(&self, block: &BlockInfo, clock: &SaleClock) -> (u64, u64) {
+        match self.clock_unit() {
+            ClockUnit::Seconds => (block.time.seconds(), clock.last_timestamp),
+            ClockUnit::Blocks => (block.height, clock.last_height),
+        }
+    }
+
+    /// Returns the rate in effect at `current_timestamp` after the threshold decay since the last
+    /// sale, along with the timestamp at which it next changes if it is still decaying. Both are
+    /// block heights for thresholds measured in blocks.
+    pub fn current_rate(&self, current_timestamp: u64, last_timestamp: u64) -> (Rate, Option<u64>) {
+        let (amount, threshold) = match (&self.rate, &self.threshold) {
+            (Rate::Flat(coin), Some(threshold)) => (coin.amount.u128(), threshold),
+            (Rate::Cw20Flat(rate), Some(threshold)) => (rate.amount.u128(), threshold),
+            // Only flat rates with a threshold decay
+            _ => return (self.rate.clone(), None),
+        };
+        // The fee only starts to decay after the first sale
+        if last_timestamp == 0 || threshold.duration == 0 {
+            return (self.rate.clone(), None);
+        }
+
+        let elapsed = current_timestamp.saturating_sub(last_timestamp);
+        let current_amount = flat_fee_amount(
+            amount,
+            Some(threshold.clone()),
+            last_timestamp + elapsed,
+            last_timestamp,
+        );
+        // No further change once the fee is clamped to the threshold value
+        let next_change = if current_amount > threshold.value {
+            let periods = elapsed / threshold.duration + 1;
+            Some(last_timestamp + periods * threshold.duration)
+        } else {
+            None
+        };
+
+        let rate = match &self.rate {
+            Rate::Cw20Flat(rate) => Rate::Cw20Flat(Cw20FlatRate {
+                address: rate.address.clone(),
+                amount: Uint128::new(current_amount),
+            }),
+            Rate::Flat(coin) => Rate::Flat(Coin::new(current_amount, coin.denom.clone())),
+            Rate::Percent(_) => self.rate.clone(),
+        };
+        (rate, next_change)
+    }
+
+    /// Returns whether the rate is deducted from the payment, either as a royalty or as a tax
+    /// included in the payment.
+    pub fn is_deducted(&self) -> bool {
+        !self.is_additive || self.is_inclusive()
+    }
+
+    /// Returns whether the rate is an additive rate included in the payment.
+    pub fn is_inclusive(&self) -> bool {
+        self.is_additive && self.inclusive.unwrap_or(false)
+    }
+}
+
+#[cw_serde]
+/// The amount a percentage rate is calculated on. Deducted rates are applied in the order they are
+/// configured, so only the rates before a rate are taken into account for its base.
+pub enum RateBase {
+    /// The gross payment amount
+    Gross,
+    /// The payment net of every deducted rate before this one
+    Net,
+    /// The payment net of the deducted rates before this one with the given descriptions
+    NetOf { descriptions: Vec<String> },
+}
+
+impl RateBase {
+    /// Returns the base amount given the gross payment and the amounts deducted by earlier rates,
+    /// along with their descriptions.
+    pub fn amount(
+        &self,
+        gross: Uint128,
+        deducted: &[(Option<String>, Uint128)],
+    ) -> Result<Uint128, ContractError> {
+        let total_deducted = deducted
+            .iter()
+            .filter(|(description, _)| match self {
+                RateBase::Gross => false,
+                RateBase::Net => true,
+                RateBase::NetOf { descriptions } => description
+                    .as_ref()
+                    .map_or(false, |description| descriptions.contains(description)),
+            })
+            .try_fold(Uint128::zero(), |sum, (_, amount)| sum.checked_add(*amount))?;
+        Ok(gross.checked_sub(total_deducted)?)
+    }
+}
+
+impl ToString for RateBase {
+    fn to_string(&self) -> String {
+        match self {
+            RateBase::Gross => "gross".to_string(),
+            RateBase::Net => "net".to_string(),
+            RateBase::NetOf { descriptions } => format!("net_of:{}", descriptions.join(",")),
+        }
+    }
+}
+
+#[cw_serde]
+/// The rounding applied to fractional fee amounts
+pub enum RoundingMode {
+    /// Round up in favour of the fee receiver
+    Up,
+    /// Round down in favour of the seller
+    Down,
+    /// Round half to even (banker's rounding)
+    HalfEven,
+}
+
+impl RoundingMode {
+    /// Multiplies `amount` by `ratio` and rounds the result using exact integer arithmetic.
+    pub fn apply(&self, amount: Uint128, ratio: Decimal) -> Result<Uint128, ContractError> {
+        self.apply_fraction(amount, ratio.numerator(), ratio.denominator())
+    }
+
+    /// Multiplies `amount` by `numerator / denominator` and rounds the result using exact integer
+    /// arithmetic.
+    pub fn apply_fraction(
+        &self,
+        amount: Uint128,
+        numerator: Uint128,
+        denominator: Uint128,
+    ) -> Result<Uint128, ContractError> {
+        let numerator = Uint256::from(amount) * Uint256::from(numerator);
+        let denominator = Uint256::from(denominator);
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+
+        let round_up = match self {
+            RoundingMode::Up => !remainder.is_zero(),
+            RoundingMode::Down => false,
+            RoundingMode::HalfEven => {
+                let doubled_remainder = remainder * Uint256::from(2u128);
+                doubled_remainder > denominator
+                    || (doubled_remainder == denominator
+                        && quotient % Uint256::from(2u128) == Uint256::from(1u128))
+            }
+        };
+        let rounded = if round_up {
+            quotient.checked_add(Uint256::from(1u128))?
+        } else {
+            quotient
+        };
+
+        Ok(Uint128::try_from(rounded).map_err(StdError::from)?)
+    }
+}
+
+#[cw_serde]
+/// The rounding applied to a rate
+pub struct Rounding {
+    pub mode: RoundingMode,
+    /// The recipient of any dust left after splitting the fee between recipients. If not set the
+    /// dust is left with the seller.
+    pub dust_recipient: Option<Recipient>,
+}
+
+/// Splits `fee` between `shares`, rounding each share with `mode`.
+///
+/// Returns the amount for each share along with the dust that is left over. Shares are allocated
+/// in order and never exceed the remaining fee, so the dust is never negative.
+pub fn split_fee(
+    fee: Uint128,
+    shares: &[Decimal],
+    mode: &RoundingMode,
+) -> Result<(Vec<Uint128>, Uint128), ContractError> {
+    let mut remaining = fee;
+    let mut amounts = vec![];
+    for share in shares.iter() {
+        let amount = std::cmp::min(mode.apply(fee, *share)?, remaining);
+        remaining -= amount;
+        amounts.push(amount);
+    }
+    Ok((amounts, remaining))
+}
+
+#[cw_serde]
+/// The kind of asset a payment is made in
+pub enum AssetKind {
+    Native,
+    Cw20,
+}
+
+#[cw_serde]
+/// Predicates restricting the payments a rate applies to, every predicate that is set must pass
+pub struct RatePredicate {
+    pub min_amount: Option<Uint128>,
+    pub max_amount: Option<Uint128>,
+    /// Native denoms or cw20 addresses the rate is applied to
+    pub allowed_assets: Option<Vec<String>>,
+    /// Native denoms or cw20 addresses the rate is not applied to
+    pub denied_assets: Option<Vec<String>>,
+    pub asset_kind: Option<AssetKind>,
+}
+
+impl RatePredicate {
+    /// Returns the name of the first predicate that the payment fails, if any.
+    ///
+    /// For cw20 payments the `denom` of `payment` is the cw20 contract address.
+    pub fn failed_predicate(&self, payment: &Coin, is_native: bool) -> Option<&'static str> {
+        if matches!(self.min_amount, Some(min) if payment.amount < min) {
+            return Some("min_amount");
+        }
+        if matches!(self.max_amount, Some(max) if payment.amount > max) {
+            return Some("max_amount");
+        }
+        if matches!(&self.allowed_assets, Some(assets) if !assets.contains(&payment.denom)) {
+            return Some("allowed_assets");
+        }
+        if matches!(&self.denied_assets, Some(assets) if assets.contains(&payment.denom)) {
+            return Some("denied_assets");
+        }
+        match self.asset_kind {
+            Some(AssetKind::Native) if !is_native => Some("asset_kind"),
+            Some(AssetKind::Cw20) if is_native => Some("asset_kind"),
+            _ => None,
+        }
+    }
+}
+
+#[cw_serde]
+/// The kind of sale that triggered the funds transfer
+pub enum SaleType {
+    Auction,
+    Fixed,
+    Offer,
+}
+
+#[cw_serde]
+/// The payload expected in `AndromedaHook::OnFundsTransfer`, describing the sale being made.
+///
+/// A missing (empty) or malformed payload is treated as having no sale context, in which case
+/// only unconditioned rates are applied.
+pub struct SaleContext {
+    pub sale_type: SaleType,
+    /// The address of the NFT collection being sold
+    pub collection: Option<String>,
+    pub token_id: Option<String>,
+    pub buyer: Option<String>,
+}
+
+impl SaleContext {
+    /// Parses the sale context from a hook payload, returning `None` if it is empty or malformed.
+    pub fn from_payload(payload: &Binary) -> Option<SaleContext> {
+        if payload.is_empty() {
+            return None;
+        }
+        from_binary(payload).ok()
+    }
+}
+
+#[cw_serde]
+/// A condition on the sale context, every field that is set must match
+pub struct SaleCondition {
+    pub sale_type: Option<SaleType>,
+    pub collection: Option<String>,
+    pub token_id: Option<String>,
+    pub buyer: Option<String>,
+}
+
+impl SaleCondition {
+    pub fn matches(&self, context: &SaleContext) -> bool {
+        fn field_matches(expected: &Option<String>, actual: &Option<String>) -> bool {
+            match expected {
+                None => true,
+                Some(expected) => actual.as_ref() == Some(expected),
+            }
+        }
+
+        self.sale_type
+            .as_ref()
+            .map_or(true, |sale_type| *sale_type == context.sale_type)
+            && field_matches(&self.collection, &context.collection)
+            && field_matches(&self.token_id, &context.token_id)
+            && field_matches(&self.buyer, &context.buyer)
+    }
 }
 
 #[cw_serde]
 /// An enum used to define various types of fees
 pub enum Rate {
-    /// A flat rate fee
+    /// A flat rate fee in a native denom
     Flat(Coin),
+    /// A flat rate fee in a cw20 token
+    Cw20Flat(Cw20FlatRate),
     /// A percentage fee
     Percent(PercentRate),
     // External(PrimitivePointer),
@@ -61,6 +1365,13 @@ pub struct PercentRate {
     pub percent: Decimal,
 }
 
+#[cw_serde]
+pub struct Cw20FlatRate {
+    /// The address of the cw20 contract
+    pub address: String,
+    pub amount: Uint128,
+}
+
 impl From<Decimal> for Rate {
     fn from(decimal: Decimal) -> Self {
         Rate::Percent(PercentRate { percent: decimal })
@@ -73,11 +1384,41 @@ impl Rate {
     pub fn is_non_zero(&self) -> Result<bool, ContractError> {
         match self {
             Rate::Flat(coin) => Ok(!coin.amount.is_zero()),
+            Rate::Cw20Flat(Cw20FlatRate { amount, .. }) => Ok(!amount.is_zero()),
             Rate::Percent(PercentRate { percent }) => Ok(!percent.is_zero()),
             // Rate::External(_) => Err(ContractError::UnexpectedExternalRate {}),
         }
     }
 
+    /// Returns whether the rate is a flat fee.
+    pub fn is_flat(&self) -> bool {
+        matches!(self, Rate::Flat(_) | Rate::Cw20Flat(_))
+    }
+
+    /// Returns the asset a flat fee is paid in as a native denom or cw20 address, along with
+    /// whether it is native. Percentage fees are paid in the asset of the payment.
+    pub fn flat_asset(&self) -> Option<(String, bool)> {
+        match self {
+            Rate::Flat(coin) => Some((coin.denom.clone(), true)),
+            Rate::Cw20Flat(Cw20FlatRate { address, .. }) => Some((address.clone(), false)),
+            Rate::Percent(_) => None,
+        }
+    }
+
+    /// Returns whether the rate is at least `floor`, rates of a different kind or asset never are.
+    pub fn is_at_least(&self, floor: &Rate) -> bool {
+        match (self, floor) {
+            (Rate::Flat(coin), Rate::Flat(floor)) => {
+                coin.denom == floor.denom && coin.amount >= floor.amount
+            }
+            (Rate::Cw20Flat(rate), Rate::Cw20Flat(floor)) => {
+                rate.address == floor.address && rate.amount >= floor.amount
+            }
+            (Rate::Percent(rate), Rate::Percent(floor)) => rate.percent >= floor.percent,
+            _ => false,
+        }
+    }
+
     /// Validates `self` and returns an "unwrapped" version of itself wherein if it is an External
     /// Rate, the actual rate value is retrieved from the Primitive Contract.
     pub fn validate(&self, querier: &QuerierWrapper) -> Result<Rate, ContractError> {
@@ -96,6 +1437,7 @@ impl Rate {
     fn get_rate(self, _querier: &QuerierWrapper) -> Result<Rate, ContractError> {
         match self {
             Rate::Flat(_) => Ok(self),
+            Rate::Cw20Flat(_) => Ok(self),
             Rate::Percent(_) => Ok(self),
             // Rate::External(primitive_pointer) => {
             //     let primitive = primitive_pointer.into_value(querier)?;
@@ -121,10 +1463,19 @@ impl Rate {
 pub struct Thredshold {
     /// decrease unit like 1 Coin
     pub unit: u64,
-    /// decrease duration in seconds like 1 hour
+    /// decrease duration in `duration_unit` like 1 hour
     pub duration: u64,
     // thredshold value
     pub value: u128,
+    /// The unit the duration is measured in, seconds if not set
+    pub duration_unit: Option<ClockUnit>,
+}
+
+#[cw_serde]
+pub enum ClockUnit {
+    Seconds,
+    /// Block heights, for chains with irregular block times
+    Blocks,
 }
 
 /// An attribute struct used for any events that involve a payment
@@ -141,6 +1492,37 @@ impl ToString for PaymentAttribute {
     }
 }
 
+/// Calculates a flat fee amount after applying the threshold decay since the last sale.
+fn flat_fee_amount(
+    amount: u128,
+    threshold: Option<Thredshold>,
+    current_timestamp: u64,
+    last_timestamp: u64,
+) -> u128 {
+    // If threshold not set or first sale, then return raw value
+    if Option::is_none(&threshold) || last_timestamp == 0 {
+        amount
+    } else {
+        let _threshold = threshold.unwrap();
+
+        // Calculate final fee based on threshold setting and timestamp
+        let time_elapsed = current_timestamp - last_timestamp;
+        let decrement_value = time_elapsed
+            .checked_div(_threshold.duration)
+            .unwrap()
+            .checked_mul(_threshold.unit)
+            .unwrap();
+        let decremented_amount = amount.saturating_sub(decrement_value as u128);
+
+        // If calculated value is lower than threshold value
+        if decremented_amount < _threshold.value {
+            _threshold.value
+        } else {
+            decremented_amount
+        }
+    }
+}
+
 /// Calculates a fee amount given a `Rate` and payment amount.
 ///
 /// ## Arguments
@@ -154,39 +1536,33 @@ pub fn calculate_fee(
     threshold: Option<Thredshold>,
     current_timestamp: u64,
     last_timestamp: u64,
+    rounding: Option<RoundingMode>,
 ) -> Result<Coin, ContractError> {
-    // Validate timestamp values
+    // Validate timestamp values, a sale in the same block as the last one pays the full fee
     ensure!(
-        last_timestamp == 0 || current_timestamp > last_timestamp,
+        last_timestamp == 0 || current_timestamp >= last_timestamp,
         ContractError::InvalidRate {}
     );
 
     match fee_rate {
         Rate::Flat(rate) => {
-            let amount = rate.amount.u128();
-
-            // If threshold not set or first sale, then return raw value
-            if Option::is_none(&threshold) || last_timestamp == 0 {
-                Ok(Coin::new(amount, rate.denom))
-            } else {
-                let _threshold = threshold.unwrap();
-
-                // Calculate final fee based on threshold setting and timestamp
-                let time_elapsed = current_timestamp - last_timestamp;
-                let decrement_value = time_elapsed
-                    .checked_div(_threshold.duration)
-                    .unwrap()
-                    .checked_mul(_threshold.unit)
-                    .unwrap();
-                let decremented_amount = amount.checked_sub(decrement_value as u128).unwrap();
-
-                // If calculated value is lower than threshold value
-                if decremented_amount < _threshold.value {
-                    Ok(Coin::new(_threshold.value, rate.denom))
-                } else {
-                    Ok(Coin::new(decremented_amount, rate.denom))
-                }
-            }
+            let amount = flat_fee_amount(
+                rate.amount.u128(),
+                threshold,
+                current_timestamp,
+                last_timestamp,
+            );
+            Ok(Coin::new(amount, rate.denom))
+        }
+        // Cw20 fees are represented as a `Coin` whose denom is the cw20 address
+        Rate::Cw20Flat(rate) => {
+            let amount = flat_fee_amount(
+                rate.amount.u128(),
+                threshold,
+                current_timestamp,
+                last_timestamp,
+            );
+            Ok(Coin::new(amount, rate.address))
         }
         Rate::Percent(PercentRate { percent }) => {
             // [COM-03] Make sure that fee_rate between 0 and 100.
@@ -195,25 +1571,60 @@ pub fn calculate_fee(
                 percent <= Decimal::one() && !percent.is_zero(),
                 ContractError::InvalidRate {}
             );
-            let mut fee_amount = payment.amount * percent;
-
-            // Always round any remainder up and prioritise the fee receiver.
-            // Inverse of percent will always exist.
-            let reversed_fee = fee_amount * percent.inv().unwrap();
-            if payment.amount > reversed_fee {
-                // [COM-1] Added checked add to fee_amount rather than direct increment
-                fee_amount = fee_amount.checked_add(1u128.into())?;
-            }
+            let fee_amount = match rounding {
+                Some(mode) => mode.apply(payment.amount, percent)?,
+                None => {
+                    let mut fee_amount = payment.amount * percent;
+
+                    // Always round any remainder up and prioritise the fee receiver.
+                    // Inverse of percent will always exist.
+                    let reversed_fee = fee_amount * percent.inv().unwrap();
+                    if payment.amount > reversed_fee {
+                        // [COM-1] Added checked add to fee_amount rather than direct increment
+                        fee_amount = fee_amount.checked_add(1u128.into())?;
+                    }
+                    fee_amount
+                }
+            };
 
             Ok(Coin::new(fee_amount.u128(), payment.denom.clone()))
         } // Rate::External(_) => Err(ContractError::UnexpectedExternalRate {}),
     }
 }
 
+/// Calculates the tax included in a payment for an inclusive percentage rate, such that the
+/// payment is the tax base plus the tax: `fee = amount * r / (1 + r)`.
+///
+/// The fee is rounded up in favour of the fee receiver if no rounding mode is given.
+pub fn calculate_inclusive_fee(
+    fee_rate: Rate,
+    payment: &Coin,
+    rounding: Option<RoundingMode>,
+) -> Result<Coin, ContractError> {
+    match fee_rate {
+        Rate::Percent(PercentRate { percent }) => {
+            ensure!(
+                percent <= Decimal::one() && !percent.is_zero(),
+                ContractError::InvalidRate {}
+            );
+            // Both parts of the ratio share the denominator of `percent`
+            let numerator = percent.numerator();
+            let denominator = percent.denominator().checked_add(numerator)?;
+            let fee_amount = rounding.unwrap_or(RoundingMode::Up).apply_fraction(
+                payment.amount,
+                numerator,
+                denominator,
+            )?;
+            Ok(Coin::new(fee_amount.u128(), payment.denom.clone()))
+        }
+        _ => Err(ContractError::InvalidRate {}),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use cosmwasm_std::{coin, Uint128};
+    use cosmwasm_std::coin;
 
     use super::*;
 
@@ -245,7 +1656,7 @@ mod tests {
         let expected = Ok(coin(5, "uluna"));
         let fee = Rate::from(Decimal::percent(4));
 
-        let received = calculate_fee(fee, &payment, None, 0, 0);
+        let received = calculate_fee(fee, &payment, None, 0, 0, None);
 
         assert_eq!(expected, received);
 
@@ -255,7 +1666,7 @@ mod tests {
             denom: "uluna".to_string(),
         });
 
-        let received = calculate_fee(fee, &payment, None, 0, 0);
+        let received = calculate_fee(fee, &payment, None, 0, 0, None);
 
         assert_eq!(expected, received);
     }
@@ -276,13 +1687,32 @@ mod tests {
                 unit: 2,
                 duration: 60,
                 value: 5,
+                duration_unit: None,
             }),
             61,
             1,
+            None,
         );
 
         assert_eq!(Ok(coin(8, "uluna")), received);
 
+        // A sale in the same block as the last one pays the full fee
+        let received = calculate_fee(
+            fee.clone(),
+            &payment,
+            Some(Thredshold {
+                unit: 2,
+                duration: 60,
+                value: 5,
+                duration_unit: None,
+            }),
+            1,
+            1,
+            None,
+        );
+
+        assert_eq!(Ok(coin(10, "uluna")), received);
+
         // After 100 seconds, fee will be 8 = (10 - 2 * 1) (Because not till 120s)
         let received = calculate_fee(
             fee.clone(),
@@ -291,9 +1721,11 @@ mod tests {
                 unit: 2,
                 duration: 60,
                 value: 5,
+                duration_unit: None,
             }),
             101,
             1,
+            None,
         );
 
         assert_eq!(Ok(coin(8, "uluna")), received);
@@ -306,11 +1738,474 @@ mod tests {
                 unit: 2,
                 duration: 60,
                 value: 5,
+                duration_unit: None,
             }),
             301,
             1,
+            None,
         );
 
         assert_eq!(Ok(coin(5, "uluna")), received);
     }
+
+    #[test]
+    fn test_rounding_mode() {
+        let amount = Uint128::new(25);
+        let percent = Decimal::percent(10);
+        assert_eq!(
+            Uint128::new(3),
+            RoundingMode::Up.apply(amount, percent).unwrap()
+        );
+        assert_eq!(
+            Uint128::new(2),
+            RoundingMode::Down.apply(amount, percent).unwrap()
+        );
+        // 2.5 rounds to the even 2, 3.5 rounds to the even 4
+        assert_eq!(
+            Uint128::new(2),
+            RoundingMode::HalfEven.apply(amount, percent).unwrap()
+        );
+        assert_eq!(
+            Uint128::new(4),
+            RoundingMode::HalfEven
+                .apply(Uint128::new(35), percent)
+                .unwrap()
+        );
+        assert_eq!(
+            Uint128::new(3),
+            RoundingMode::HalfEven
+                .apply(Uint128::new(26), percent)
+                .unwrap()
+        );
+
+        let payment = coin(25, "uluna");
+        let received = calculate_fee(
+            Rate::from(percent),
+            &payment,
+            None,
+            0,
+            0,
+            Some(RoundingMode::Down),
+        );
+        assert_eq!(Ok(coin(2, "uluna")), received);
+    }
+
+    #[test]
+    fn test_calculate_inclusive_fee() {
+        // 110 including 10% tax is a tax base of 100 and a tax of 10
+        let payment = coin(110, "uluna");
+        let fee = Rate::from(Decimal::percent(10));
+        assert_eq!(
+            Ok(coin(10, "uluna")),
+            calculate_inclusive_fee(fee.clone(), &payment, None)
+        );
+
+        // 100 / 1.1 = 9.09...
+        let payment = coin(100, "uluna");
+        assert_eq!(
+            Ok(coin(10, "uluna")),
+            calculate_inclusive_fee(fee.clone(), &payment, None)
+        );
+        assert_eq!(
+            Ok(coin(9, "uluna")),
+            calculate_inclusive_fee(fee, &payment, Some(RoundingMode::HalfEven))
+        );
+
+        let fee = Rate::Flat(coin(10, "uluna"));
+        assert_eq!(
+            Err(ContractError::InvalidRate {}),
+            calculate_inclusive_fee(fee, &payment, None)
+        );
+    }
+
+    #[test]
+    fn test_split_fee() {
+        let shares = vec![Decimal::percent(50), Decimal::percent(50)];
+        let (amounts, dust) = split_fee(Uint128::new(5), &shares, &RoundingMode::Down).unwrap();
+        assert_eq!(vec![Uint128::new(2), Uint128::new(2)], amounts);
+        assert_eq!(Uint128::new(1), dust);
+
+        // Rounding up never allocates more than the fee
+        let (amounts, dust) = split_fee(Uint128::new(5), &shares, &RoundingMode::Up).unwrap();
+        assert_eq!(vec![Uint128::new(3), Uint128::new(2)], amounts);
+        assert_eq!(Uint128::zero(), dust);
+    }
+
+    #[test]
+    fn test_rate_base_amount() {
+        let gross = Uint128::new(100);
+        let deducted = vec![
+            (Some("marketplace".to_string()), Uint128::new(5)),
+            (None, Uint128::new(2)),
+        ];
+        assert_eq!(gross, RateBase::Gross.amount(gross, &deducted).unwrap());
+        assert_eq!(
+            Uint128::new(93),
+            RateBase::Net.amount(gross, &deducted).unwrap()
+        );
+        let base = RateBase::NetOf {
+            descriptions: vec!["marketplace".to_string()],
+        };
+        assert_eq!(Uint128::new(95), base.amount(gross, &deducted).unwrap());
+        assert_eq!("net_of:marketplace", base.to_string());
+    }
+
+    #[test]
+    fn test_current_rate() {
+        let rate_info = RateInfo {
+            rate: Rate::Flat(coin(10, "uluna")),
+            is_additive: true,
+            description: None,
+            recipients: vec![],
+            threshold: Some(Thredshold {
+                unit: 2,
+                duration: 60,
+                value: 5,
+                duration_unit: None,
+            }),
+            condition: None,
+            predicate: None,
+            rounding: None,
+            shares: None,
+            base: None,
+            inclusive: None,
+            dynamic: None,
+            clock: None,
+        };
+
+        // Before the first sale the fee doesn't decay
+        assert_eq!(
+            (rate_info.rate.clone(), None),
+            rate_info.current_rate(100, 0)
+        );
+
+        // After 100 seconds the fee is 8 and drops to 6 at 120 seconds
+        assert_eq!(
+            (Rate::Flat(coin(8, "uluna")), Some(121)),
+            rate_info.current_rate(101, 1)
+        );
+
+        // Once clamped to the threshold value the fee no longer changes
+        assert_eq!(
+            (Rate::Flat(coin(5, "uluna")), None),
+            rate_info.current_rate(301, 1)
+        );
+    }
+
+    #[test]
+    fn test_sale_context_from_payload() {
+        assert_eq!(None, SaleContext::from_payload(&Binary::default()));
+        assert_eq!(
+            None,
+            SaleContext::from_payload(&Binary::from("not json".as_bytes()))
+        );
+
+        let context = SaleContext {
+            sale_type: SaleType::Auction,
+            collection: Some("collection".to_string()),
+            token_id: None,
+            buyer: None,
+        };
+        let payload = cosmwasm_std::to_binary(&context).unwrap();
+        assert_eq!(Some(context), SaleContext::from_payload(&payload));
+    }
+
+    #[test]
+    fn test_sale_condition_matches() {
+        let context = SaleContext {
+            sale_type: SaleType::Auction,
+            collection: Some("collection".to_string()),
+            token_id: Some("1".to_string()),
+            buyer: None,
+        };
+        let mut condition = SaleCondition {
+            sale_type: Some(SaleType::Auction),
+            collection: None,
+            token_id: None,
+            buyer: None,
+        };
+        assert!(condition.matches(&context));
+
+        condition.collection = Some("other".to_string());
+        assert!(!condition.matches(&context));
+
+        condition.collection = None;
+        condition.buyer = Some("buyer".to_string());
+        assert!(!condition.matches(&context));
+
+        condition.buyer = None;
+        condition.sale_type = Some(SaleType::Fixed);
+        assert!(!condition.matches(&context));
+    }
+
+    #[test]
+    fn test_rate_predicate() {
+        let mut predicate = RatePredicate {
+            min_amount: Some(Uint128::new(10)),
+            max_amount: Some(Uint128::new(1000)),
+            allowed_assets: None,
+            denied_assets: None,
+            asset_kind: None,
+        };
+        assert_eq!(None, predicate.failed_predicate(&coin(10, "uusd"), true));
+        assert_eq!(
+            Some("min_amount"),
+            predicate.failed_predicate(&coin(9, "uusd"), true)
+        );
+        assert_eq!(
+            Some("max_amount"),
+            predicate.failed_predicate(&coin(1001, "uusd"), true)
+        );
+
+        predicate.allowed_assets = Some(vec!["uusd".to_string()]);
+        assert_eq!(
+            Some("allowed_assets"),
+            predicate.failed_predicate(&coin(100, "uluna"), true)
+        );
+
+        predicate.allowed_assets = None;
+        predicate.denied_assets = Some(vec!["uluna".to_string()]);
+        assert_eq!(
+            Some("denied_assets"),
+            predicate.failed_predicate(&coin(100, "uluna"), true)
+        );
+
+        predicate.asset_kind = Some(AssetKind::Native);
+        assert_eq!(
+            Some("asset_kind"),
+            predicate.failed_predicate(&coin(100, "cw20_address"), false)
+        );
+        assert_eq!(None, predicate.failed_predicate(&coin(100, "uusd"), true));
+    }
+
+    #[test]
+    fn test_accepted_assets() {
+        let accepted_assets = AcceptedAssets {
+            native_denoms: vec!["uusd".to_string()],
+            cw20_addresses: vec!["cw20_address".to_string()],
+        };
+        assert!(accepted_assets.accepts("uusd", true));
+        assert!(accepted_assets.accepts("cw20_address", false));
+        assert!(!accepted_assets.accepts("uluna", true));
+        // A native denom is not accepted as a cw20 address
+        assert!(!accepted_assets.accepts("uusd", false));
+    }
+
+    #[test]
+    fn test_clock_reset_policy() {
+        let policy = ClockResetPolicy::MinAmount {
+            min_amounts: vec![coin(100, "uusd")],
+        };
+        // The first sale always starts the clock
+        assert_eq!(Some(1000), policy.next_timestamp(&coin(1, "uusd"), 1000, 0));
+        assert_eq!(None, policy.next_timestamp(&coin(99, "uusd"), 1000, 400));
+        assert_eq!(
+            Some(1000),
+            policy.next_timestamp(&coin(100, "uusd"), 1000, 400)
+        );
+        assert_eq!(None, policy.next_timestamp(&coin(100, "uluna"), 1000, 400));
+
+        let policy = ClockResetPolicy::Proportional {
+            full_reset_amounts: vec![coin(100, "uusd")],
+        };
+        assert_eq!(
+            Some(550),
+            policy.next_timestamp(&coin(25, "uusd"), 1000, 400)
+        );
+        assert_eq!(
+            Some(1000),
+            policy.next_timestamp(&coin(500, "uusd"), 1000, 400)
+        );
+        assert_eq!(None, policy.next_timestamp(&coin(0, "uusd"), 1000, 400));
+
+        let policy = ClockResetPolicy::Gradual { max_recovery: 120 };
+        assert_eq!(
+            Some(520),
+            policy.next_timestamp(&coin(1, "uusd"), 1000, 400)
+        );
+        assert_eq!(
+            Some(1000),
+            policy.next_timestamp(&coin(1, "uusd"), 1000, 950)
+        );
+    }
+
+    #[test]
+    fn test_dynamic_fee() {
+        let dynamic = DynamicFee {
+            target_interval: 80,
+            max_change: Decimal::percent(10),
+            min: Decimal::percent(1),
+            max: Decimal::percent(10),
+        };
+        let state = dynamic.initial_state(Decimal::percent(5));
+        assert_eq!(80, state.average_interval);
+
+        // The first sale only starts measuring the intervals
+        let state = dynamic.record_sale(&state, 1000);
+        assert_eq!(Decimal::percent(5), state.percent);
+        assert_eq!(1000, state.last_sale);
+
+        // Faster sales than targeted raise the rate
+        let state = dynamic.record_sale(&state, 1016);
+        assert_eq!(72, state.average_interval);
+        assert_eq!(Decimal::from_ratio(505u128, 10000u128), state.percent);
+
+        // Slower sales lower it
+        let state = dynamic.record_sale(&state, 1736);
+        assert_eq!(153, state.average_interval);
+        assert_eq!(
+            Decimal::from_ratio(45891875u128, 1000000000u128),
+            state.percent
+        );
+
+        // The rate stays within its bounds
+        let state = DynamicFeeState {
+            percent: Decimal::percent(10),
+            average_interval: 0,
+            last_sale: 2000,
+        };
+        assert_eq!(
+            Decimal::percent(10),
+            dynamic.record_sale(&state, 2000).percent
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let circuit_breaker = CircuitBreaker {
+            window: 100,
+            max_fees: vec![coin(50, "uusd")],
+            max_volume_share: Some(Decimal::percent(20)),
+            max_sale_ratio: Some(Decimal::percent(50)),
+            mode: PauseMode::SkipFees,
+        };
+        let mut state = BreakerState::default();
+
+        assert_eq!(
+            Some("sale fees exceed 0.5 of the payment".to_string()),
+            circuit_breaker.violation(&state, 0, &coin(100, "uusd"), Uint128::new(51))
+        );
+
+        circuit_breaker.record(&mut state, 0, &coin(100, "uusd"), Uint128::new(20));
+        circuit_breaker.record(&mut state, 5, &coin(100, "uusd"), Uint128::new(10));
+        assert_eq!(
+            (Uint128::new(200), Uint128::new(30)),
+            circuit_breaker.window_totals(&state, 5, "uusd")
+        );
+        assert_eq!(
+            Some("fees exceed 0.2 of the volume".to_string()),
+            circuit_breaker.violation(&state, 5, &coin(10, "uusd"), Uint128::new(5))
+        );
+        assert_eq!(
+            Some("fees of 55 exceed 50uusd".to_string()),
+            circuit_breaker.violation(&state, 5, &coin(1000, "uusd"), Uint128::new(25))
+        );
+        // Other assets are tracked separately
+        assert_eq!(
+            None,
+            circuit_breaker.violation(&state, 5, &coin(100, "uluna"), Uint128::new(20))
+        );
+
+        // The first sale leaves the window along with its bucket
+        assert_eq!(
+            (Uint128::new(100), Uint128::new(10)),
+            circuit_breaker.window_totals(&state, 105, "uusd")
+        );
+        circuit_breaker.record(&mut state, 111, &coin(100, "uusd"), Uint128::new(10));
+        assert_eq!(
+            vec![1, 11],
+            state
+                .buckets
+                .iter()
+                .map(|bucket| bucket.index)
+                .collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn test_rate_commitment_violation() {
+        let rate_info = RateInfo {
+            rate: Rate::from(Decimal::percent(10)),
+            is_additive: false,
+            description: Some("royalty".to_string()),
+            recipients: vec![Recipient::from_string("creator")],
+            threshold: None,
+            condition: None,
+            predicate: None,
+            rounding: None,
+            shares: None,
+            base: None,
+            inclusive: None,
+            dynamic: None,
+            clock: None,
+        };
+        let commitment = RateCommitment {
+            description: "royalty".to_string(),
+            floor: Some(Rate::from(Decimal::percent(5))),
+            expiration: Some(100),
+            immutable: false,
+        };
+        assert!(commitment.is_active(99));
+        assert!(!commitment.is_active(100));
+
+        let lowered = RateInfo {
+            rate: Rate::from(Decimal::percent(5)),
+            ..rate_info.clone()
+        };
+        assert_eq!(None, commitment.violation(&rate_info, Some(&lowered)));
+
+        let below_floor = RateInfo {
+            rate: Rate::from(Decimal::percent(4)),
+            ..rate_info.clone()
+        };
+        assert_eq!(
+            Some("the rate can't drop below its floor"),
+            commitment.violation(&rate_info, Some(&below_floor))
+        );
+        // A flat fee is never at least a percentage floor
+        let flat = RateInfo {
+            rate: Rate::Flat(coin(100, "uusd")),
+            ..rate_info.clone()
+        };
+        assert_eq!(
+            Some("the rate can't drop below its floor"),
+            commitment.violation(&rate_info, Some(&flat))
+        );
+        // A dynamic rate can be adjusted down to its minimum
+        let dynamic = RateInfo {
+            dynamic: Some(DynamicFee {
+                target_interval: 10,
+                max_change: Decimal::percent(50),
+                min: Decimal::percent(1),
+                max: Decimal::percent(20),
+            }),
+            ..rate_info.clone()
+        };
+        assert_eq!(
+            Some("the rate can't drop below its floor"),
+            commitment.violation(&rate_info, Some(&dynamic))
+        );
+
+        let moved = RateInfo {
+            recipients: vec![Recipient::from_string("other")],
+            ..rate_info.clone()
+        };
+        assert_eq!(
+            Some("its recipients can't be removed"),
+            commitment.violation(&rate_info, Some(&moved))
+        );
+        assert_eq!(
+            Some("the rate can't be removed"),
+            commitment.violation(&rate_info, None)
+        );
+
+        let immutable = RateCommitment {
+            immutable: true,
+            ..commitment
+        };
+        assert_eq!(
+            Some("the rate is immutable"),
+            immutable.violation(&rate_info, Some(&lowered))
+        );
+    }
 }
@@ -1,10 +1,25 @@
 use std::vec;
 
 #[cfg(not(feature = "library"))]
-use crate::state::{Config, CONFIG};
+use crate::state::{
+    save_rates, Config, ACCEPTED_ASSETS, APPROVERS, AUDIT_LOG, BREAKER_STATE, CIRCUIT_BREAKER,
+    CLOCK_RESET_POLICY, COMMITMENTS, CONFIG, DEDUCTION_CAP, DYNAMIC_FEES, HOOK_CALLERS,
+    NEXT_ROTATION_ID, PAUSE, PENDING_ROTATIONS, PROPOSALS, RATE_VERSIONS, RATE_VERSIONS_BY_HEIGHT,
+    RATE_VERSIONS_BY_TIME, RECIPIENT_RATES, ROLES, ROTATION_VETO_PERIOD, SALE_CLOCKS,
+    TRANSFER_RATES,
+};
 use andromeda_modules::rates::{
-    calculate_fee, ExecuteMsg, InstantiateMsg, MigrateMsg, PaymentAttribute, PaymentsResponse,
-    QueryMsg, RateInfo,
+    calculate_fee, calculate_inclusive_fee, split_fee, AcceptedAssets, AcceptedAssetsResponse,
+    ApproverSet, ApproversResponse, AuditChange, AuditEntry, AuditLogResponse, BreakerTrip,
+    CircuitBreaker, CircuitBreakerResponse, ClockResetPolicy, ClockResetPolicyResponse, ClockUnit,
+    CommitmentsResponse, CurrentRate, CurrentRatesResponse, Cw20FlatRate, DeductionCapResponse,
+    DynamicFeeState, ExecuteMsg, HookCallersResponse, IndexedRate, InstantiateMsg, MigrateMsg,
+    Pause, PauseMode, PauseStatusResponse, PaymentAttribute, PaymentsResponse, PendingRotation,
+    PendingRotationsResponse, PercentRate, PricingResponse, Proposal, ProposalAction,
+    ProposalsResponse, QueryMsg, Rate, RateCommitment, RateFilter, RateHistoryResponse, RateInfo,
+    RateVersion, RatesAtResponse, RatesForRecipientResponse, RatesResponse, RoleHolder,
+    RoleHoldersResponse, Roles, RolesResponse, RotationVetoPeriodResponse, RoundingMode, SaleClock,
+    SaleClockResponse, SaleContext, TransferRatesResponse,
 };
 use andromeda_std::{
     ado_base::{
@@ -12,16 +27,20 @@ use andromeda_std::{
         InstantiateMsg as BaseInstantiateMsg,
     },
     ado_contract::ADOContract,
+    amp::{messages::AMPPkt, recipient::Recipient},
     common::{context::ExecuteContext, deduct_funds, encode_binary, Funds},
     error::{from_semver, ContractError},
 };
 
+use cosmwasm_schema::serde::Serialize;
 use cosmwasm_std::{
-    attr, coin, ensure, Binary, Coin, Deps, DepsMut, Env, Event, MessageInfo, Response, SubMsg,
+    attr, coin, ensure, to_vec, Binary, BlockInfo, Coin, Decimal, Deps, DepsMut, Env, Event,
+    MessageInfo, Order, Response, StdResult, Storage, SubMsg, Uint128,
 };
 use cosmwasm_std::{entry_point, WasmMsg};
 use cw2::{get_contract_version, set_contract_version};
 use cw20::Cw20Coin;
+use cw_storage_plus::Bound;
 use cw_utils::nonpayable;
 use semver::Version;
 // version info for migration info
@@ -36,11 +55,17 @@ pub fn instantiate(
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    validate_rates(&msg.rates)?;
     let config = Config {
         rates: msg.rates,
         last_timestamp: 0,
+        last_height: 0,
     };
-    CONFIG.save(deps.storage, &config)?;
+    save_rates(deps.storage, &env.block, &config)?;
+
+    let transfer_rates = msg.transfer_rates.unwrap_or_default();
+    validate_transfer_rates(&transfer_rates)?;
+    TRANSFER_RATES.save(deps.storage, &transfer_rates)?;
 
     let inst_resp = ADOContract::default().instantiate(
         deps.storage,
@@ -79,196 +104,2980 @@ pub fn execute(
 pub fn handle_execute(ctx: ExecuteContext, msg: ExecuteMsg) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::UpdateRates { rates } => execute_update_rates(ctx, rates),
+        ExecuteMsg::UpdateRate { description, rate } => execute_update_rate(ctx, description, rate),
+        ExecuteMsg::UpdateRoles { address, roles } => execute_update_roles(ctx, address, roles),
+        ExecuteMsg::UpdateTransferRates { rates } => execute_update_transfer_rates(ctx, rates),
+        ExecuteMsg::UpdateAcceptedAssets { accepted_assets } => {
+            execute_update_accepted_assets(ctx, accepted_assets)
+        }
+        ExecuteMsg::UpdateDeductionCap { cap } => execute_update_deduction_cap(ctx, cap),
         ExecuteMsg::UpdateSaleTimestamp {
             last_timestamp,
-        } => execute_update_sale_timestamp(ctx, last_timestamp),
+            last_height,
+            payment,
+        } => execute_update_sale_timestamp(ctx, last_timestamp, last_height, payment),
+        ExecuteMsg::UpdateRateTimestamp {
+            description,
+            last_timestamp,
+            last_height,
+            payment,
+        } => execute_update_rate_timestamp(ctx, description, last_timestamp, last_height, payment),
+        ExecuteMsg::UpdateApprovers { approver_set } => execute_update_approvers(ctx, approver_set),
+        ExecuteMsg::Propose { action, expiration } => execute_propose(ctx, action, expiration),
+        ExecuteMsg::Approve { proposal_id } => execute_approve(ctx, proposal_id),
+        ExecuteMsg::RotateRecipient {
+            description,
+            recipient,
+        } => execute_rotate_recipient(ctx, description, recipient),
+        ExecuteMsg::ApplyRotation { rotation_id } => execute_apply_rotation(ctx, rotation_id),
+        ExecuteMsg::VetoRotation { rotation_id } => execute_veto_rotation(ctx, rotation_id),
+        ExecuteMsg::UpdateRotationVetoPeriod { period } => {
+            execute_update_rotation_veto_period(ctx, period)
+        }
+        ExecuteMsg::CommitRate { commitment } => execute_commit_rate(ctx, commitment),
+        ExecuteMsg::Pause { mode, until } => execute_pause(ctx, mode, until),
+        ExecuteMsg::Unpause {} => execute_unpause(ctx),
+        ExecuteMsg::UpdateCircuitBreaker { circuit_breaker } => {
+            execute_update_circuit_breaker(ctx, circuit_breaker)
+        }
+        ExecuteMsg::ResetCircuitBreaker {} => execute_reset_circuit_breaker(ctx),
+        ExecuteMsg::UpdateHookCallers { callers } => execute_update_hook_callers(ctx, callers),
+        ExecuteMsg::RecordFees { payment, fees } => execute_record_fees(ctx, payment, fees),
+        ExecuteMsg::UpdateClockResetPolicy { policy } => {
+            execute_update_clock_reset_policy(ctx, policy)
+        }
+        ExecuteMsg::RecordSale {} => execute_record_sale(ctx),
+        ExecuteMsg::UpdateOwner { .. } => execute_update_owner(ctx, msg),
         _ => ADOContract::default().execute(ctx, msg),
     }
 }
 
+/// Appends a change to the audit log, returning the event describing it.
+fn audit(
+    storage: &mut dyn Storage,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    action: &str,
+    changes: Vec<AuditChange>,
+) -> Result<Event, ContractError> {
+    let id = AUDIT_LOG
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |id| id + 1);
+    let entry = AuditEntry {
+        id,
+        actor: info.sender.to_string(),
+        origin: amp_ctx.as_ref().map(|pkt| pkt.ctx.get_origin()),
+        time: env.block.time.seconds(),
+        action: action.to_string(),
+        changes,
+    };
+    AUDIT_LOG.save(storage, id, &entry)?;
+
+    let mut event = Event::new("audit")
+        .add_attribute("id", id.to_string())
+        .add_attribute("actor", entry.actor)
+        .add_attribute("time", entry.time.to_string())
+        .add_attribute("action", entry.action);
+    if let Some(origin) = entry.origin {
+        event = event.add_attribute("origin", origin);
+    }
+    for change in entry.changes {
+        if let Some(old) = change.old {
+            event = event.add_attribute(format!("{}.old", change.field), old);
+        }
+        if let Some(new) = change.new {
+            event = event.add_attribute(format!("{}.new", change.field), new);
+        }
+    }
+    Ok(event)
+}
+
+fn to_json<T: Serialize>(value: &T) -> StdResult<String> {
+    Ok(String::from_utf8_lossy(&to_vec(value)?).into_owned())
+}
+
+/// The change to a value, none if it is unchanged.
+fn diff_value<T: Serialize + PartialEq>(
+    field: &str,
+    old: Option<&T>,
+    new: Option<&T>,
+) -> StdResult<Option<AuditChange>> {
+    if old == new {
+        return Ok(None);
+    }
+    Ok(Some(AuditChange {
+        field: field.to_string(),
+        old: old.map(to_json).transpose()?,
+        new: new.map(to_json).transpose()?,
+    }))
+}
+
+/// The changes to a list, compared entry by entry.
+fn diff_list<T: Serialize + PartialEq>(
+    field: &str,
+    old: &[T],
+    new: &[T],
+) -> StdResult<Vec<AuditChange>> {
+    (0..old.len().max(new.len()))
+        .filter_map(|i| diff_value(&format!("{field}[{i}]"), old.get(i), new.get(i)).transpose())
+        .collect()
+}
+
+/// Records ownership changes in the audit log before handing them to the ADO contract, the entry
+/// is reverted along with the change if it fails.
+fn execute_update_owner(ctx: ExecuteContext, msg: ExecuteMsg) -> Result<Response, ContractError> {
+    let new_owner = match &msg {
+        ExecuteMsg::UpdateOwner { address } => address.clone(),
+        _ => return ADOContract::default().execute(ctx, msg),
+    };
+    let owner = ADOContract::default()
+        .query_contract_owner(ctx.deps.storage)?
+        .owner;
+    let event = audit(
+        ctx.deps.storage,
+        &ctx.env,
+        &ctx.info,
+        &ctx.amp_ctx,
+        "update_owner",
+        diff_value("owner", Some(&owner), Some(&new_owner))?
+            .into_iter()
+            .collect(),
+    )?;
+
+    Ok(ADOContract::default().execute(ctx, msg)?.add_event(event))
+}
+
 fn execute_update_rates(
     ctx: ExecuteContext,
     rates: Vec<RateInfo>,
 ) -> Result<Response, ContractError> {
-    let ExecuteContext { deps, info, .. } = ctx;
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
     nonpayable(&info)?;
 
     ensure!(
         ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
         ContractError::Unauthorized {}
     );
-    let mut config = CONFIG.load(deps.storage)?;
-    config.rates = rates;
-    CONFIG.save(deps.storage, &config)?;
+    ensure!(
+        APPROVERS.may_load(deps.storage)?.is_none(),
+        ContractError::CustomError {
+            msg: "Rate changes have to be proposed to the approvers".to_string(),
+        }
+    );
+    apply_rates(deps, &env, &info, &amp_ctx, rates)
+}
+
+fn execute_update_rate(
+    ctx: ExecuteContext,
+    description: String,
+    rate: RateInfo,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    // The owner only manages every rate while there are no approvers to propose changes to
+    let is_owner = ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?
+        && APPROVERS.may_load(deps.storage)?.is_none();
+    let is_manager = ROLES
+        .may_load(deps.storage, info.sender.as_str())?
+        .map_or(false, |roles| roles.managed_rates.contains(&description));
+    ensure!(is_owner || is_manager, ContractError::Unauthorized {});
+    ensure!(
+        rate.description.as_ref() == Some(&description),
+        ContractError::CustomError {
+            msg: format!("Rate {description} can't be renamed"),
+        }
+    );
 
-    Ok(Response::new().add_attributes(vec![attr("action", "update_rates")]))
+    let mut rates = CONFIG.load(deps.storage)?.rates;
+    let index = rates
+        .iter()
+        .position(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Rate {description} does not exist"),
+        })?;
+    rates[index] = rate;
+    apply_rates(deps, &env, &info, &amp_ctx, rates)
 }
 
-fn execute_update_sale_timestamp(
+fn execute_update_roles(
     ctx: ExecuteContext,
-    last_timestamp: u64,
+    address: String,
+    roles: Option<Roles>,
 ) -> Result<Response, ContractError> {
-    let ExecuteContext { deps, info, .. } = ctx;
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
     nonpayable(&info)?;
 
-    // Not sure if called from hook, sender can be owner or contract
-    /*
     ensure!(
         ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
         ContractError::Unauthorized {}
     );
-    */
+    ensure!(
+        APPROVERS.may_load(deps.storage)?.is_none(),
+        ContractError::CustomError {
+            msg: "Role changes have to be proposed to the approvers".to_string(),
+        }
+    );
+    apply_roles(deps, &env, &info, &amp_ctx, address, roles)
+}
 
-    let mut config = CONFIG.load(deps.storage)?;
-    config.last_timestamp = last_timestamp;
-    CONFIG.save(deps.storage, &config)?;
+/// Saves the roles of an address, recording the change in the audit log.
+fn apply_roles(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    address: String,
+    roles: Option<Roles>,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(&address)?;
+    let changes = diff_value(
+        &format!("roles.{address}"),
+        ROLES.may_load(deps.storage, &address)?.as_ref(),
+        roles.as_ref(),
+    )?
+    .into_iter()
+    .collect();
+    match roles {
+        Some(roles) => ROLES.save(deps.storage, &address, &roles)?,
+        None => ROLES.remove(deps.storage, &address),
+    }
+    let event = audit(deps.storage, env, info, amp_ctx, "update_roles", changes)?;
 
-    Ok(Response::new().add_attributes(vec![attr("action", "update_sale_timestamp")]))
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_roles")])
+        .add_event(event))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
-    // New version
-    let version: Version = CONTRACT_VERSION.parse().map_err(from_semver)?;
+fn execute_rotate_recipient(
+    ctx: ExecuteContext,
+    description: String,
+    recipient: Recipient,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
 
-    // Old version
-    let stored = get_contract_version(deps.storage)?;
-    let storage_version: Version = stored.version.parse().map_err(from_semver)?;
+    let config = CONFIG.load(deps.storage)?;
+    let rate_info = config
+        .rates
+        .iter()
+        .find(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Rate {description} does not exist"),
+        })?;
+    let old_recipient = rate_info
+        .recipients
+        .iter()
+        .find(|receiver| receiver.get_addr() == info.sender.as_str())
+        .cloned()
+        .ok_or(ContractError::Unauthorized {})?;
 
-    let contract = ADOContract::default();
+    let period = match ROTATION_VETO_PERIOD.may_load(deps.storage)? {
+        Some(period) => period,
+        None => {
+            return apply_rotation(
+                deps,
+                &env,
+                &info,
+                &amp_ctx,
+                description,
+                old_recipient,
+                recipient,
+            )
+        }
+    };
+    let id = NEXT_ROTATION_ID.may_load(deps.storage)?.unwrap_or_default();
+    NEXT_ROTATION_ID.save(deps.storage, &(id + 1))?;
+    let rotation = PendingRotation {
+        id,
+        description,
+        old_recipient,
+        new_recipient: recipient,
+        executable_at: env.block.time.seconds() + period,
+    };
+    PENDING_ROTATIONS.save(deps.storage, id, &rotation)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "rotate_recipient")])
+        .add_event(
+            rotation_event(
+                &rotation.description,
+                &rotation.old_recipient,
+                &rotation.new_recipient,
+                "pending",
+            )
+            .add_attribute("rotation_id", id.to_string())
+            .add_attribute("executable_at", rotation.executable_at.to_string()),
+        ))
+}
 
+fn execute_apply_rotation(
+    ctx: ExecuteContext,
+    rotation_id: u64,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let rotation = PENDING_ROTATIONS.load(deps.storage, rotation_id)?;
     ensure!(
-        stored.contract == CONTRACT_NAME,
-        ContractError::CannotMigrate {
-            previous_contract: stored.contract,
+        env.block.time.seconds() >= rotation.executable_at,
+        ContractError::CustomError {
+            msg: format!(
+                "Rotation {rotation_id} can't be applied before {}",
+                rotation.executable_at
+            ),
         }
     );
+    PENDING_ROTATIONS.remove(deps.storage, rotation_id);
+
+    let res = apply_rotation(
+        deps,
+        &env,
+        &info,
+        &amp_ctx,
+        rotation.description,
+        rotation.old_recipient,
+        rotation.new_recipient,
+    )?;
+    Ok(res.add_attribute("rotation_id", rotation_id.to_string()))
+}
+
+fn execute_veto_rotation(ctx: ExecuteContext, rotation_id: u64) -> Result<Response, ContractError> {
+    let ExecuteContext { deps, info, .. } = ctx;
+    nonpayable(&info)?;
 
-    // New version has to be newer/greater than the old version
     ensure!(
-        storage_version < version,
-        ContractError::CannotMigrate {
-            previous_contract: stored.version,
-        }
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
     );
+    let rotation = PENDING_ROTATIONS.load(deps.storage, rotation_id)?;
+    PENDING_ROTATIONS.remove(deps.storage, rotation_id);
 
-    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "veto_rotation")])
+        .add_event(
+            rotation_event(
+                &rotation.description,
+                &rotation.old_recipient,
+                &rotation.new_recipient,
+                "vetoed",
+            )
+            .add_attribute("rotation_id", rotation_id.to_string()),
+        ))
+}
 
-    // Update the ADOContract's version
-    contract.execute_update_version(deps)?;
+fn execute_update_rotation_veto_period(
+    ctx: ExecuteContext,
+    period: Option<u64>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
 
-    Ok(Response::default())
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let changes = diff_value(
+        "rotation_veto_period",
+        ROTATION_VETO_PERIOD.may_load(deps.storage)?.as_ref(),
+        period.as_ref(),
+    )?
+    .into_iter()
+    .collect();
+    match period {
+        Some(period) => ROTATION_VETO_PERIOD.save(deps.storage, &period)?,
+        None => ROTATION_VETO_PERIOD.remove(deps.storage),
+    }
+    let event = audit(
+        deps.storage,
+        &env,
+        &info,
+        &amp_ctx,
+        "update_rotation_veto_period",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_rotation_veto_period")])
+        .add_event(event))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
-    match msg {
-        QueryMsg::AndrHook(msg) => handle_andromeda_hook(deps, env, msg),
-        QueryMsg::Payments {} => encode_binary(&query_payments(deps)?),
-        _ => ADOContract::default().query(deps, env, msg),
-    }
+fn rotation_event(
+    description: &str,
+    old_recipient: &Recipient,
+    new_recipient: &Recipient,
+    status: &str,
+) -> Event {
+    Event::new("rotate_recipient")
+        .add_attribute("rate", description)
+        .add_attribute("old_recipient", old_recipient.get_addr())
+        .add_attribute("new_recipient", new_recipient.get_addr())
+        .add_attribute("status", status)
 }
 
-fn handle_andromeda_hook(
-    deps: Deps,
-    env: Env,
-    msg: AndromedaHook,
-) -> Result<Binary, ContractError> {
-    match msg {
-        AndromedaHook::OnFundsTransfer { amount, .. } => {
-            encode_binary(&query_deducted_funds(deps, env, amount)?)
+/// Replaces a recipient of a rate, recording the change in the audit log.
+///
+/// Commitments aren't checked as the recipient chose to be replaced.
+fn apply_rotation(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    description: String,
+    old_recipient: Recipient,
+    new_recipient: Recipient,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    let old_rates = config.rates.clone();
+    let receiver = config
+        .rates
+        .iter_mut()
+        .filter(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .flat_map(|rate_info| rate_info.recipients.iter_mut())
+        .find(|receiver| **receiver == old_recipient)
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!(
+                "{} is no longer a recipient of rate {description}",
+                old_recipient.get_addr()
+            ),
+        })?;
+    *receiver = new_recipient.clone();
+
+    let changes = diff_list("rates", &old_rates, &config.rates)?;
+    save_rates(deps.storage, &env.block, &config)?;
+    let event = audit(
+        deps.storage,
+        env,
+        info,
+        amp_ctx,
+        "rotate_recipient",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "rotate_recipient")])
+        .add_event(rotation_event(
+            &description,
+            &old_recipient,
+            &new_recipient,
+            "applied",
+        ))
+        .add_event(event))
+}
+
+/// Validates and saves the rates, recording the change in the audit log.
+fn apply_rates(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    rates: Vec<RateInfo>,
+) -> Result<Response, ContractError> {
+    validate_rates(&rates)?;
+    validate_deduction_cap(&rates, DEDUCTION_CAP.may_load(deps.storage)?)?;
+    let mut config = CONFIG.load(deps.storage)?;
+    validate_commitments(&deps.as_ref(), env, &config.rates, &rates)?;
+    let changes = diff_list("rates", &config.rates, &rates)?;
+    config.rates = rates;
+    save_rates(deps.storage, &env.block, &config)?;
+    let event = audit(deps.storage, env, info, amp_ctx, "update_rates", changes)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_rates")])
+        .add_event(event))
+}
+
+/// Validates that changing the rates doesn't break any active commitment.
+fn validate_commitments(
+    deps: &Deps,
+    env: &Env,
+    old_rates: &[RateInfo],
+    new_rates: &[RateInfo],
+) -> Result<(), ContractError> {
+    for commitment in COMMITMENTS.range(deps.storage, None, None, Order::Ascending) {
+        let (description, commitment) = commitment?;
+        if !commitment.is_active(env.block.time.seconds()) {
+            continue;
+        }
+        let has_description =
+            |rate_info: &&RateInfo| rate_info.description.as_ref() == Some(&description);
+        let old = match old_rates.iter().find(has_description) {
+            Some(old) => old,
+            None => continue,
+        };
+        let new = new_rates.iter().find(has_description);
+        if let Some(violation) = commitment.violation(old, new) {
+            return Err(commitment_error(&description, &commitment, violation));
         }
-        _ => Ok(encode_binary(&None::<Response>)?),
     }
+    Ok(())
 }
 
-fn query_payments(deps: Deps) -> Result<PaymentsResponse, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
-
-    Ok(PaymentsResponse {
-        payments: config.rates,
-        last_timestamp: config.last_timestamp,
-    })
+/// Validates that none of the committed rates for which `is_affected` holds are still active, for
+/// changes outside the rates themselves that could lower their fee.
+fn validate_uncommitted(
+    deps: &Deps,
+    env: &Env,
+    rates: &[RateInfo],
+    is_affected: impl Fn(&RateInfo) -> bool,
+    violation: &str,
+) -> Result<(), ContractError> {
+    for commitment in COMMITMENTS.range(deps.storage, None, None, Order::Ascending) {
+        let (description, commitment) = commitment?;
+        if !commitment.is_active(env.block.time.seconds()) {
+            continue;
+        }
+        let is_committed = rates.iter().any(|rate_info| {
+            rate_info.description.as_ref() == Some(&description) && is_affected(rate_info)
+        });
+        if is_committed {
+            return Err(commitment_error(&description, &commitment, violation));
+        }
+    }
+    Ok(())
 }
 
-//NOTE Currently set as pub for testing
-pub fn query_deducted_funds(
-    deps: Deps,
-    env: Env,
-    funds: Funds,
-) -> Result<OnFundsTransferResponse, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
+fn commitment_error(
+    description: &str,
+    commitment: &RateCommitment,
+    violation: &str,
+) -> ContractError {
+    let until = commitment
+        .expiration
+        .map_or("permanently".to_string(), |expiration| {
+            format!("until {expiration}")
+        });
+    ContractError::CustomError {
+        msg: format!("Rate {description} is committed {until}, {violation}"),
+    }
+}
 
-    let current_timestamp = env.block.time.seconds();
-    let last_timestamp = config.last_timestamp;
+fn execute_commit_rate(
+    ctx: ExecuteContext,
+    commitment: RateCommitment,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
 
-    let mut msgs: Vec<SubMsg> = vec![];
-    let mut events: Vec<Event> = vec![];
-    let (coin, is_native): (Coin, bool) = match funds {
-        Funds::Native(coin) => (coin, true),
-        Funds::Cw20(cw20_coin) => (coin(cw20_coin.amount.u128(), cw20_coin.address), false),
-    };
-    let mut leftover_funds = vec![coin.clone()];
-    for rate_info in config.rates.iter() {
-        let event_name = if rate_info.is_additive {
-            "tax"
-        } else {
-            "royalty"
-        };
-        let mut event = Event::new(event_name);
-        if let Some(desc) = &rate_info.description {
-            event = event.add_attribute("description", desc);
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let description = commitment.description.clone();
+    let existing = COMMITMENTS.may_load(deps.storage, &description)?;
+    ensure!(
+        !existing.as_ref().map_or(false, |existing| existing
+            .is_active(env.block.time.seconds())),
+        ContractError::CustomError {
+            msg: format!("Rate {description} is already committed"),
         }
-        let rate = rate_info.rate.validate(&deps.querier)?;
-        let fee = calculate_fee(rate, &coin, rate_info.threshold.clone(), current_timestamp, last_timestamp)?;
-        for receiver in rate_info.recipients.iter() {
-            if !rate_info.is_additive {
-                deduct_funds(&mut leftover_funds, &fee)?;
-                event = event.add_attribute("deducted", fee.to_string());
-            }
-            event = event.add_attribute(
-                "payment",
-                PaymentAttribute {
-                    receiver: receiver.get_addr(),
-                    amount: fee.clone(),
-                }
-                .to_string(),
-            );
-            let msg = if is_native {
-                receiver.generate_direct_msg(&deps, vec![fee.clone()])?
-            } else {
-                receiver.generate_msg_cw20(
-                    &deps,
-                    Cw20Coin {
-                        amount: fee.amount,
-                        address: fee.denom.to_string(),
-                    },
-                )?
-            };
-            msgs.push(msg);
+    );
+    ensure!(
+        commitment.is_active(env.block.time.seconds()),
+        ContractError::CustomError {
+            msg: "The commitment expiration must be in the future".to_string(),
         }
-        events.push(event);
+    );
+    // The rate has to meet its own commitment, even once it has decayed or adjusted down
+    let rate_info = CONFIG
+        .load(deps.storage)?
+        .rates
+        .into_iter()
+        .find(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Rate {description} does not exist"),
+        })?;
+    if let Some(floor) = &commitment.floor {
+        ensure!(
+            rate_info.lowest_rate().is_at_least(floor),
+            ContractError::InvalidRate {}
+        );
     }
 
-    // Update last sale timestamp as current
-    msgs.push(SubMsg::new(WasmMsg::Execute {
-        contract_addr: env.contract.address.to_string(),
-        msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
-            last_timestamp: current_timestamp,
-        })?,
-        funds: vec![],
-    }));
+    let changes = diff_value(
+        &format!("commitments.{description}"),
+        existing.as_ref(),
+        Some(&commitment),
+    )?
+    .into_iter()
+    .collect();
+    COMMITMENTS.save(deps.storage, &description, &commitment)?;
+    let event = audit(deps.storage, &env, &info, &amp_ctx, "commit_rate", changes)?;
 
-    Ok(OnFundsTransferResponse {
-        msgs,
-        leftover_funds: if is_native {
-            Funds::Native(leftover_funds[0].clone())
-        } else {
-            Funds::Cw20(Cw20Coin {
-                amount: leftover_funds[0].amount,
-                address: coin.denom,
-            })
-        },
-        events,
-    })
+    Ok(Response::new()
+        .add_attributes(vec![
+            attr("action", "commit_rate"),
+            attr("rate", description),
+        ])
+        .add_event(event))
+}
+
+fn execute_update_approvers(
+    ctx: ExecuteContext,
+    approver_set: Option<ApproverSet>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    ensure!(
+        APPROVERS.may_load(deps.storage)?.is_none(),
+        ContractError::CustomError {
+            msg: "Approver changes have to be proposed to the current approvers".to_string(),
+        }
+    );
+    apply_approvers(deps, &env, &info, &amp_ctx, approver_set)
+}
+
+/// Validates that the approvers are unique addresses and the threshold can be met.
+fn validate_approver_set(deps: &Deps, approver_set: &ApproverSet) -> Result<(), ContractError> {
+    for (i, approver) in approver_set.approvers.iter().enumerate() {
+        deps.api.addr_validate(approver)?;
+        ensure!(
+            !approver_set.approvers[..i].contains(approver),
+            ContractError::CustomError {
+                msg: format!("Duplicate approver {approver}"),
+            }
+        );
+    }
+    ensure!(
+        approver_set.threshold > 0
+            && approver_set.threshold as usize <= approver_set.approvers.len(),
+        ContractError::CustomError {
+            msg: "The approval threshold must be between 1 and the number of approvers".to_string(),
+        }
+    );
+    Ok(())
+}
+
+/// Validates and saves the approvers, recording the change in the audit log.
+fn apply_approvers(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    approver_set: Option<ApproverSet>,
+) -> Result<Response, ContractError> {
+    let current = APPROVERS.may_load(deps.storage)?;
+    let mut changes: Vec<AuditChange> =
+        diff_value("approver_set", current.as_ref(), approver_set.as_ref())?
+            .into_iter()
+            .collect();
+    // Rate managers appointed by the owner alone would keep changing rates without approvals, so
+    // they have to be appointed again through proposals once approvers are installed
+    if current.is_none() && approver_set.is_some() {
+        changes.extend(revoke_managed_rates(deps.storage)?);
+    }
+    match approver_set {
+        Some(approver_set) => {
+            validate_approver_set(&deps.as_ref(), &approver_set)?;
+            APPROVERS.save(deps.storage, &approver_set)?;
+        }
+        None => APPROVERS.remove(deps.storage),
+    }
+    let event = audit(
+        deps.storage,
+        env,
+        info,
+        amp_ctx,
+        "update_approvers",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_approvers")])
+        .add_event(event))
+}
+
+/// Removes every rate from the rates managed by each address, returning the changes made.
+fn revoke_managed_rates(storage: &mut dyn Storage) -> Result<Vec<AuditChange>, ContractError> {
+    let managers = ROLES
+        .range(storage, None, None, Order::Ascending)
+        .filter(|item| {
+            item.as_ref()
+                .map_or(true, |(_, roles)| !roles.managed_rates.is_empty())
+        })
+        .collect::<StdResult<Vec<(String, Roles)>>>()?;
+    let mut changes = vec![];
+    for (address, roles) in managers {
+        let revoked = roles.operator.then(|| Roles {
+            operator: true,
+            managed_rates: vec![],
+        });
+        match &revoked {
+            Some(revoked) => ROLES.save(storage, &address, revoked)?,
+            None => ROLES.remove(storage, &address),
+        }
+        changes.extend(diff_value(
+            &format!("roles.{address}"),
+            Some(&roles),
+            revoked.as_ref(),
+        )?);
+    }
+    Ok(changes)
+}
+
+/// Loads the approvers and checks the sender is one of them.
+fn load_approvers(deps: &Deps, info: &MessageInfo) -> Result<ApproverSet, ContractError> {
+    let approver_set =
+        APPROVERS
+            .may_load(deps.storage)?
+            .ok_or_else(|| ContractError::CustomError {
+                msg: "No approvers are set".to_string(),
+            })?;
+    ensure!(
+        approver_set.approvers.contains(&info.sender.to_string()),
+        ContractError::Unauthorized {}
+    );
+    Ok(approver_set)
+}
+
+fn execute_propose(
+    ctx: ExecuteContext,
+    action: ProposalAction,
+    expiration: u64,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let approver_set = load_approvers(&deps.as_ref(), &info)?;
+    ensure!(
+        expiration > env.block.time.seconds(),
+        ContractError::CustomError {
+            msg: "The proposal expiration must be in the future".to_string(),
+        }
+    );
+    // Invalid changes are rejected up front, they are validated again once applied
+    match &action {
+        ProposalAction::UpdateRates { rates } => {
+            validate_rates(rates)?;
+            validate_deduction_cap(rates, DEDUCTION_CAP.may_load(deps.storage)?)?;
+        }
+        ProposalAction::UpdateApprovers {
+            approver_set: Some(approver_set),
+        } => validate_approver_set(&deps.as_ref(), approver_set)?,
+        ProposalAction::UpdateApprovers { approver_set: None } => {}
+        ProposalAction::UpdateRoles { address, .. } => {
+            deps.api.addr_validate(address)?;
+        }
+        ProposalAction::UpdateTransferRates { rates } => validate_transfer_rates(rates)?,
+        ProposalAction::UpdateDeductionCap { cap } => validate_cap(&deps.as_ref(), &env, *cap)?,
+    }
+
+    let id = PROPOSALS
+        .keys(deps.storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |id| id + 1);
+    let proposal = Proposal {
+        id,
+        proposer: info.sender.to_string(),
+        action,
+        approvals: vec![info.sender.to_string()],
+        expiration,
+        executed: false,
+    };
+    let res = Response::new().add_attributes(vec![
+        attr("action", "propose"),
+        attr("proposal_id", id.to_string()),
+    ]);
+    apply_if_approved(deps, &env, &info, &amp_ctx, &approver_set, proposal, res)
+}
+
+fn execute_approve(ctx: ExecuteContext, proposal_id: u64) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let approver_set = load_approvers(&deps.as_ref(), &info)?;
+    let mut proposal = PROPOSALS
+        .may_load(deps.storage, proposal_id)?
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Proposal {proposal_id} does not exist"),
+        })?;
+    ensure!(
+        !proposal.executed,
+        ContractError::CustomError {
+            msg: format!("Proposal {proposal_id} has already been executed"),
+        }
+    );
+    ensure!(
+        proposal.expiration > env.block.time.seconds(),
+        ContractError::CustomError {
+            msg: format!("Proposal {proposal_id} has expired"),
+        }
+    );
+    ensure!(
+        !proposal.approvals.contains(&info.sender.to_string()),
+        ContractError::CustomError {
+            msg: format!(
+                "Proposal {proposal_id} has already been approved by {}",
+                info.sender
+            ),
+        }
+    );
+    proposal.approvals.push(info.sender.to_string());
+
+    let res = Response::new().add_attributes(vec![
+        attr("action", "approve"),
+        attr("proposal_id", proposal_id.to_string()),
+    ]);
+    apply_if_approved(deps, &env, &info, &amp_ctx, &approver_set, proposal, res)
+}
+
+/// Saves the proposal, applying its change once enough approvers have approved it.
+///
+/// Approvals of addresses that have since been removed from the approvers aren't counted.
+fn apply_if_approved(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    approver_set: &ApproverSet,
+    mut proposal: Proposal,
+    res: Response,
+) -> Result<Response, ContractError> {
+    let approvals = proposal
+        .approvals
+        .iter()
+        .filter(|approval| approver_set.approvers.contains(approval))
+        .count();
+    if approvals < approver_set.threshold as usize {
+        PROPOSALS.save(deps.storage, proposal.id, &proposal)?;
+        return Ok(res);
+    }
+
+    proposal.executed = true;
+    PROPOSALS.save(deps.storage, proposal.id, &proposal)?;
+    let applied = match proposal.action {
+        ProposalAction::UpdateRates { rates } => apply_rates(deps, env, info, amp_ctx, rates)?,
+        ProposalAction::UpdateApprovers { approver_set } => {
+            apply_approvers(deps, env, info, amp_ctx, approver_set)?
+        }
+        ProposalAction::UpdateRoles { address, roles } => {
+            apply_roles(deps, env, info, amp_ctx, address, roles)?
+        }
+        ProposalAction::UpdateTransferRates { rates } => {
+            apply_transfer_rates(deps, env, info, amp_ctx, rates)?
+        }
+        ProposalAction::UpdateDeductionCap { cap } => apply_cap(deps, env, info, amp_ctx, cap)?,
+    };
+    Ok(res
+        .add_attribute("executed", "true")
+        .add_events(applied.events))
+}
+
+/// Validates that rate descriptions are unique, that the shares of each rate match its recipients
+/// and add up to the whole fee, that only additive percentage rates are inclusive and that only
+/// percentage rates have a base.
+fn validate_rates(rates: &[RateInfo]) -> Result<(), ContractError> {
+    for (index, rate_info) in rates.iter().enumerate() {
+        // Commitments, roles, clocks and dynamic fees all look rates up by their description
+        if let Some(description) = &rate_info.description {
+            ensure!(
+                !rates[..index]
+                    .iter()
+                    .any(|other| other.description.as_ref() == Some(description)),
+                ContractError::CustomError {
+                    msg: format!("Rate description {description} is used more than once"),
+                }
+            );
+        }
+        // The decay is counted in whole durations
+        if let Some(threshold) = &rate_info.threshold {
+            ensure!(threshold.duration > 0, ContractError::InvalidRate {});
+        }
+        if rate_info.inclusive == Some(true) {
+            ensure!(
+                rate_info.is_additive && matches!(rate_info.rate, Rate::Percent(_)),
+                ContractError::InvalidRate {}
+            );
+        }
+        // Flat fees don't depend on the amount they are charged on
+        if rate_info.base.is_some() {
+            ensure!(
+                matches!(rate_info.rate, Rate::Percent(_)),
+                ContractError::InvalidRate {}
+            );
+        }
+        if let Some(shares) = &rate_info.shares {
+            ensure!(
+                shares.len() == rate_info.recipients.len()
+                    && shares
+                        .iter()
+                        .fold(Decimal::zero(), |sum, share| sum + *share)
+                        == Decimal::one(),
+                ContractError::InvalidRate {}
+            );
+        }
+        // Clocks of their own are tracked by the rate's description and only drive thresholds
+        if rate_info.clock.is_some() {
+            ensure!(
+                rate_info.description.is_some() && rate_info.threshold.is_some(),
+                ContractError::InvalidRate {}
+            );
+        }
+        // Dynamic rates are percentages tracked by their description, a rate of zero never moves
+        if let Some(dynamic) = &rate_info.dynamic {
+            ensure!(
+                matches!(rate_info.rate, Rate::Percent(_))
+                    && rate_info.description.is_some()
+                    && dynamic.target_interval > 0
+                    && dynamic.max_change <= Decimal::one()
+                    && !dynamic.min.is_zero()
+                    && dynamic.min <= dynamic.max
+                    && dynamic.max <= Decimal::one(),
+                ContractError::InvalidRate {}
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Validates that the royalty percentage rates don't add up to more than the deduction cap.
+///
+/// Flat fees can't be checked ahead of a sale, they are scaled down along with every other royalty
+/// if the cap is exceeded. Inclusive taxes are required in full, so they aren't capped. Rates
+/// without shares pay every recipient the full fee, so they count once per recipient.
+fn validate_deduction_cap(rates: &[RateInfo], cap: Option<Decimal>) -> Result<(), ContractError> {
+    let cap = match cap {
+        Some(cap) => cap,
+        None => return Ok(()),
+    };
+    let total_percent = rates
+        .iter()
+        .filter(|rate_info| !rate_info.is_additive)
+        .filter_map(|rate_info| {
+            let percent = match (&rate_info.rate, &rate_info.dynamic) {
+                // Dynamic rates can rise up to their max
+                (Rate::Percent(_), Some(dynamic)) => dynamic.max,
+                (Rate::Percent(PercentRate { percent }), None) => *percent,
+                _ => return None,
+            };
+            let payees = match rate_info.shares {
+                Some(_) => 1,
+                None => rate_info.recipients.len() as u128,
+            };
+            Some(percent * Decimal::from_ratio(payees, 1u128))
+        })
+        .fold(Decimal::zero(), |sum, percent| sum + percent);
+    ensure!(
+        total_percent <= cap,
+        ContractError::CustomError {
+            msg: format!("Deducted rates of {total_percent} exceed the deduction cap of {cap}"),
+        }
+    );
+    Ok(())
+}
+
+fn execute_update_deduction_cap(
+    ctx: ExecuteContext,
+    cap: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    ensure!(
+        APPROVERS.may_load(deps.storage)?.is_none(),
+        ContractError::CustomError {
+            msg: "Deduction cap changes have to be proposed to the approvers".to_string(),
+        }
+    );
+    apply_cap(deps, &env, &info, &amp_ctx, cap)
+}
+
+fn validate_cap(deps: &Deps, env: &Env, cap: Option<Decimal>) -> Result<(), ContractError> {
+    if let Some(cap) = cap {
+        ensure!(cap <= Decimal::one(), ContractError::InvalidRate {});
+        let config = CONFIG.load(deps.storage)?;
+        validate_deduction_cap(&config.rates, Some(cap))?;
+        // A lower cap scales down the deducted rates
+        let is_lowered = DEDUCTION_CAP
+            .may_load(deps.storage)?
+            .map_or(true, |current| cap < current);
+        if is_lowered {
+            validate_uncommitted(
+                deps,
+                env,
+                &config.rates,
+                |rate_info| !rate_info.is_additive,
+                "the deduction cap can't be lowered",
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn apply_cap(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    cap: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    validate_cap(&deps.as_ref(), env, cap)?;
+    let changes = diff_value(
+        "cap",
+        DEDUCTION_CAP.may_load(deps.storage)?.as_ref(),
+        cap.as_ref(),
+    )?
+    .into_iter()
+    .collect();
+    match cap {
+        Some(cap) => DEDUCTION_CAP.save(deps.storage, &cap)?,
+        None => DEDUCTION_CAP.remove(deps.storage),
+    }
+
+    let event = audit(
+        deps.storage,
+        env,
+        info,
+        amp_ctx,
+        "update_deduction_cap",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_deduction_cap")])
+        .add_event(event))
+}
+
+fn execute_update_transfer_rates(
+    ctx: ExecuteContext,
+    rates: Vec<RateInfo>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    ensure!(
+        APPROVERS.may_load(deps.storage)?.is_none(),
+        ContractError::CustomError {
+            msg: "Transfer rate changes have to be proposed to the approvers".to_string(),
+        }
+    );
+    apply_transfer_rates(deps, &env, &info, &amp_ctx, rates)
+}
+
+fn apply_transfer_rates(
+    deps: DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    amp_ctx: &Option<AMPPkt>,
+    rates: Vec<RateInfo>,
+) -> Result<Response, ContractError> {
+    validate_transfer_rates(&rates)?;
+    let changes = diff_list(
+        "transfer_rates",
+        &TRANSFER_RATES.may_load(deps.storage)?.unwrap_or_default(),
+        &rates,
+    )?;
+    TRANSFER_RATES.save(deps.storage, &rates)?;
+    let event = audit(
+        deps.storage,
+        env,
+        info,
+        amp_ctx,
+        "update_transfer_rates",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_transfer_rates")])
+        .add_event(event))
+}
+
+/// Transfer rates are paid by the transferrer and therefore can only be flat fees.
+///
+/// The whole fee is paid to every recipient, so the options that only apply to sale fees are
+/// rejected rather than silently ignored.
+fn validate_transfer_rates(rates: &[RateInfo]) -> Result<(), ContractError> {
+    for rate_info in rates.iter() {
+        ensure!(
+            rate_info.rate.is_flat()
+                && rate_info.threshold.is_none()
+                && rate_info.condition.is_none()
+                && rate_info.predicate.is_none()
+                && rate_info.rounding.is_none()
+                && rate_info.shares.is_none()
+                && rate_info.base.is_none()
+                && rate_info.inclusive.is_none()
+                && rate_info.dynamic.is_none()
+                && rate_info.clock.is_none(),
+            ContractError::InvalidRate {}
+        );
+    }
+    Ok(())
+}
+
+fn execute_update_accepted_assets(
+    ctx: ExecuteContext,
+    accepted_assets: Option<AcceptedAssets>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let changes = diff_value(
+        "accepted_assets",
+        ACCEPTED_ASSETS.may_load(deps.storage)?.as_ref(),
+        accepted_assets.as_ref(),
+    )?
+    .into_iter()
+    .collect();
+    match accepted_assets {
+        Some(accepted_assets) => {
+            for address in accepted_assets.cw20_addresses.iter() {
+                deps.api.addr_validate(address)?;
+            }
+            ACCEPTED_ASSETS.save(deps.storage, &accepted_assets)?;
+        }
+        None => ACCEPTED_ASSETS.remove(deps.storage),
+    }
+
+    let event = audit(
+        deps.storage,
+        &env,
+        &info,
+        &amp_ctx,
+        "update_accepted_assets",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_accepted_assets")])
+        .add_event(event))
+}
+
+fn is_owner_or_operator(deps: &Deps, address: &str) -> Result<bool, ContractError> {
+    let is_operator = ROLES
+        .may_load(deps.storage, address)?
+        .map_or(false, |roles| roles.operator);
+    Ok(is_operator || ADOContract::default().is_contract_owner(deps.storage, address)?)
+}
+
+/// Returns whether the address is allowed to report sales. The funds transfer hook is a query, so
+/// its reports are sent by the marketplace calling it rather than by this contract.
+fn is_hook_caller(deps: &Deps, address: &str) -> Result<bool, ContractError> {
+    Ok(HOOK_CALLERS
+        .may_load(deps.storage)?
+        .map_or(false, |callers| {
+            callers.iter().any(|caller| caller == address)
+        }))
+}
+
+fn execute_pause(
+    ctx: ExecuteContext,
+    mode: PauseMode,
+    until: Option<u64>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        is_owner_or_operator(&deps.as_ref(), info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let pause = Pause { mode, until };
+    ensure!(
+        pause.is_active(env.block.time.seconds()),
+        ContractError::CustomError {
+            msg: "The pause must end in the future".to_string(),
+        }
+    );
+    let changes = diff_value(
+        "pause",
+        active_pause(&deps.as_ref(), &env)?.as_ref(),
+        Some(&pause),
+    )?
+    .into_iter()
+    .collect();
+    PAUSE.save(deps.storage, &pause)?;
+    let event = audit(deps.storage, &env, &info, &amp_ctx, "pause", changes)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "pause")])
+        .add_event(event))
+}
+
+fn execute_unpause(ctx: ExecuteContext) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        is_owner_or_operator(&deps.as_ref(), info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let changes = diff_value("pause", active_pause(&deps.as_ref(), &env)?.as_ref(), None)?
+        .into_iter()
+        .collect();
+    PAUSE.remove(deps.storage);
+    let event = audit(deps.storage, &env, &info, &amp_ctx, "unpause", changes)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "unpause")])
+        .add_event(event))
+}
+
+/// Loads the pause, none if fee collection isn't paused or the pause has ended.
+fn active_pause(deps: &Deps, env: &Env) -> Result<Option<Pause>, ContractError> {
+    Ok(PAUSE
+        .may_load(deps.storage)?
+        .filter(|pause| pause.is_active(env.block.time.seconds())))
+}
+
+/// Returns how fees are collected while paused or after the circuit breaker tripped, none if
+/// they are collected as usual. A pause takes precedence.
+fn safe_mode(deps: &Deps, env: &Env) -> Result<Option<PauseMode>, ContractError> {
+    if let Some(pause) = active_pause(deps, env)? {
+        return Ok(Some(pause.mode));
+    }
+    let is_tripped = BREAKER_STATE
+        .may_load(deps.storage)?
+        .map_or(false, |state| state.trip.is_some());
+    if !is_tripped {
+        return Ok(None);
+    }
+    Ok(CIRCUIT_BREAKER
+        .may_load(deps.storage)?
+        .map(|circuit_breaker| circuit_breaker.mode))
+}
+
+fn execute_update_circuit_breaker(
+    ctx: ExecuteContext,
+    circuit_breaker: Option<CircuitBreaker>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let changes = diff_value(
+        "circuit_breaker",
+        CIRCUIT_BREAKER.may_load(deps.storage)?.as_ref(),
+        circuit_breaker.as_ref(),
+    )?
+    .into_iter()
+    .collect();
+    match circuit_breaker {
+        Some(circuit_breaker) => {
+            ensure!(
+                circuit_breaker.window > 0,
+                ContractError::CustomError {
+                    msg: "The circuit breaker window can't be empty".to_string(),
+                }
+            );
+            CIRCUIT_BREAKER.save(deps.storage, &circuit_breaker)?;
+        }
+        None => {
+            CIRCUIT_BREAKER.remove(deps.storage);
+            BREAKER_STATE.remove(deps.storage);
+        }
+    }
+    let event = audit(
+        deps.storage,
+        &env,
+        &info,
+        &amp_ctx,
+        "update_circuit_breaker",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_circuit_breaker")])
+        .add_event(event))
+}
+
+fn execute_reset_circuit_breaker(ctx: ExecuteContext) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        is_owner_or_operator(&deps.as_ref(), info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let trip = BREAKER_STATE
+        .may_load(deps.storage)?
+        .and_then(|state| state.trip);
+    let changes = diff_value("circuit_breaker_trip", trip.as_ref(), None)?
+        .into_iter()
+        .collect();
+    BREAKER_STATE.remove(deps.storage);
+    let event = audit(
+        deps.storage,
+        &env,
+        &info,
+        &amp_ctx,
+        "reset_circuit_breaker",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "reset_circuit_breaker")])
+        .add_event(event))
+}
+
+fn execute_update_hook_callers(
+    ctx: ExecuteContext,
+    callers: Vec<String>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    for caller in callers.iter() {
+        deps.api.addr_validate(caller)?;
+    }
+    let changes = diff_list(
+        "hook_callers",
+        &HOOK_CALLERS.may_load(deps.storage)?.unwrap_or_default(),
+        &callers,
+    )?;
+    HOOK_CALLERS.save(deps.storage, &callers)?;
+    let event = audit(
+        deps.storage,
+        &env,
+        &info,
+        &amp_ctx,
+        "update_hook_callers",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_hook_callers")])
+        .add_event(event))
+}
+
+fn execute_record_fees(
+    ctx: ExecuteContext,
+    payment: Funds,
+    fees: Uint128,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        is_hook_caller(&deps.as_ref(), info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+
+    let mut res = Response::new().add_attributes(vec![attr("action", "record_fees")]);
+    let circuit_breaker = match CIRCUIT_BREAKER.may_load(deps.storage)? {
+        Some(circuit_breaker) => circuit_breaker,
+        None => return Ok(res),
+    };
+    let current_timestamp = env.block.time.seconds();
+    let (payment, _) = split_funds(payment);
+    let mut state = BREAKER_STATE.may_load(deps.storage)?.unwrap_or_default();
+    if state.trip.is_none() {
+        if let Some(reason) = circuit_breaker.violation(&state, current_timestamp, &payment, fees) {
+            res = res.add_event(
+                Event::new("circuit_breaker")
+                    .add_attribute("tripped", reason.clone())
+                    .add_attribute("time", current_timestamp.to_string()),
+            );
+            state.trip = Some(BreakerTrip {
+                time: current_timestamp,
+                reason,
+            });
+        }
+    }
+    circuit_breaker.record(&mut state, current_timestamp, &payment, fees);
+    BREAKER_STATE.save(deps.storage, &state)?;
+
+    Ok(res)
+}
+
+fn execute_record_sale(ctx: ExecuteContext) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        is_hook_caller(&deps.as_ref(), info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+
+    let config = CONFIG.load(deps.storage)?;
+    let current_timestamp = env.block.time.seconds();
+    let mut res = Response::new().add_attributes(vec![attr("action", "record_sale")]);
+    for rate_info in config.rates.iter() {
+        if let (Some(dynamic), Some(description), Some(state)) = (
+            &rate_info.dynamic,
+            &rate_info.description,
+            dynamic_state(deps.storage, rate_info)?,
+        ) {
+            let state = dynamic.record_sale(&state, current_timestamp);
+            DYNAMIC_FEES.save(deps.storage, description, &state)?;
+            res = res.add_event(
+                Event::new("dynamic_rate")
+                    .add_attribute("description", description)
+                    .add_attribute("percent", state.percent.to_string())
+                    .add_attribute("average_interval", state.average_interval.to_string()),
+            );
+        }
+    }
+
+    Ok(res)
+}
+
+/// Returns the current state of a dynamic rate, none if the rate isn't dynamic.
+///
+/// Rates without a recorded sale start at their configured percentage. The percentage is kept
+/// within the rate's current bounds in case they changed since the last sale.
+fn dynamic_state(
+    storage: &dyn Storage,
+    rate_info: &RateInfo,
+) -> StdResult<Option<DynamicFeeState>> {
+    let (dynamic, description, percent) =
+        match (&rate_info.dynamic, &rate_info.description, &rate_info.rate) {
+            (Some(dynamic), Some(description), Rate::Percent(PercentRate { percent })) => {
+                (dynamic, description, *percent)
+            }
+            _ => return Ok(None),
+        };
+    let state = match DYNAMIC_FEES.may_load(storage, description)? {
+        Some(state) => DynamicFeeState {
+            percent: state.percent.max(dynamic.min).min(dynamic.max),
+            ..state
+        },
+        None => dynamic.initial_state(percent),
+    };
+    Ok(Some(state))
+}
+
+fn execute_update_clock_reset_policy(
+    ctx: ExecuteContext,
+    policy: Option<ClockResetPolicy>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let current = CLOCK_RESET_POLICY.may_load(deps.storage)?;
+    // Any policy can hold back the clock of the rates decaying on the shared clock, lowering their
+    // fee, so only removing it is allowed while they are committed
+    if policy.is_some() && policy != current {
+        let config = CONFIG.load(deps.storage)?;
+        validate_uncommitted(
+            &deps.as_ref(),
+            &env,
+            &config.rates,
+            |rate_info| rate_info.threshold.is_some() && rate_info.clock.is_none(),
+            "the clock reset policy can only be removed",
+        )?;
+    }
+    let changes = diff_value("clock_reset_policy", current.as_ref(), policy.as_ref())?
+        .into_iter()
+        .collect();
+    match policy {
+        Some(policy) => CLOCK_RESET_POLICY.save(deps.storage, &policy)?,
+        None => CLOCK_RESET_POLICY.remove(deps.storage),
+    }
+    let event = audit(
+        deps.storage,
+        &env,
+        &info,
+        &amp_ctx,
+        "update_clock_reset_policy",
+        changes,
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![attr("action", "update_clock_reset_policy")])
+        .add_event(event))
+}
+
+fn execute_update_sale_timestamp(
+    ctx: ExecuteContext,
+    last_timestamp: u64,
+    last_height: Option<u64>,
+    payment: Option<Funds>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let mut config = CONFIG.load(deps.storage)?;
+    let current = config.sale_clock();
+    let clock = SaleClock {
+        last_timestamp,
+        last_height: last_height.unwrap_or(current.last_height),
+    };
+    let policy = CLOCK_RESET_POLICY.may_load(deps.storage)?;
+    let sale_clock = payment.and_then(|payment| {
+        let (payment, _) = split_funds(payment);
+        next_sale_clock(
+            policy.as_ref(),
+            &payment,
+            &env.block,
+            &current,
+            &shared_clock_units(&config),
+        )
+    });
+    let is_audited = check_clock_move(&deps.as_ref(), &env, &info, &clock, sale_clock)?;
+    validate_uncommitted(
+        &deps.as_ref(),
+        &env,
+        &config.rates,
+        |rate_info| {
+            rate_info.threshold.is_some()
+                && rate_info.clock.is_none()
+                && sets_clock_back(rate_info, &env.block, &current, &clock)
+        },
+        "its sale clock can't be set back",
+    )?;
+
+    let changes = diff_sale_clock("", &current, &clock)?;
+    config.last_timestamp = clock.last_timestamp;
+    config.last_height = clock.last_height;
+    CONFIG.save(deps.storage, &config)?;
+
+    let mut res = Response::new().add_attributes(vec![attr("action", "update_sale_timestamp")]);
+    if is_audited {
+        let event = audit(
+            deps.storage,
+            &env,
+            &info,
+            &amp_ctx,
+            "update_sale_timestamp",
+            changes,
+        )?;
+        res = res.add_event(event);
+    }
+    Ok(res)
+}
+
+fn execute_update_rate_timestamp(
+    ctx: ExecuteContext,
+    description: String,
+    last_timestamp: u64,
+    last_height: Option<u64>,
+    payment: Option<Funds>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps,
+        info,
+        env,
+        amp_ctx,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let config = CONFIG.load(deps.storage)?;
+    let (rate_info, own_clock) = config
+        .rates
+        .iter()
+        .find(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .and_then(|rate_info| {
+            rate_info
+                .clock
+                .as_ref()
+                .map(|own_clock| (rate_info, own_clock))
+        })
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Rate {description} has no clock of its own"),
+        })?;
+    let current = SALE_CLOCKS
+        .may_load(deps.storage, &description)?
+        .unwrap_or_default();
+    let clock = SaleClock {
+        last_timestamp,
+        last_height: last_height.unwrap_or(current.last_height),
+    };
+    let sale_clock = payment.and_then(|payment| {
+        let (payment, _) = split_funds(payment);
+        next_sale_clock(
+            own_clock.reset_policy.as_ref(),
+            &payment,
+            &env.block,
+            &current,
+            &[rate_info.clock_unit()],
+        )
+    });
+    let is_audited = check_clock_move(&deps.as_ref(), &env, &info, &clock, sale_clock)?;
+    validate_uncommitted(
+        &deps.as_ref(),
+        &env,
+        &config.rates,
+        |rate_info| {
+            rate_info.description.as_ref() == Some(&description)
+                && sets_clock_back(rate_info, &env.block, &current, &clock)
+        },
+        "its sale clock can't be set back",
+    )?;
+
+    let changes = diff_sale_clock(&format!("{description}."), &current, &clock)?;
+    SALE_CLOCKS.save(deps.storage, &description, &clock)?;
+
+    let mut res = Response::new().add_attributes(vec![
+        attr("action", "update_rate_timestamp"),
+        attr("description", description),
+    ]);
+    if is_audited {
+        let event = audit(
+            deps.storage,
+            &env,
+            &info,
+            &amp_ctx,
+            "update_rate_timestamp",
+            changes,
+        )?;
+        res = res.add_event(event);
+    }
+    Ok(res)
+}
+
+/// Checks that a sale clock can be moved to `clock`, returning whether the move is an
+/// administrative change to be audited.
+///
+/// Only the owner or an operator can move a clock, apart from registered hook callers reporting a
+/// sale, which can only move it to `sale_clock`, where its reset policy moves it after the payment.
+/// No one can set a clock past the current block time or height, as later sales would fail until
+/// the block catches up.
+fn check_clock_move(
+    deps: &Deps,
+    env: &Env,
+    info: &MessageInfo,
+    clock: &SaleClock,
+    sale_clock: Option<SaleClock>,
+) -> Result<bool, ContractError> {
+    let is_sale = sale_clock.as_ref() == Some(clock) && is_hook_caller(deps, info.sender.as_str())?;
+    if !is_sale {
+        ensure!(
+            is_owner_or_operator(deps, info.sender.as_str())?,
+            ContractError::Unauthorized {}
+        );
+    }
+    ensure!(
+        clock.last_timestamp <= env.block.time.seconds() && clock.last_height <= env.block.height,
+        ContractError::CustomError {
+            msg: "A sale clock can't be set past the current block".to_string(),
+        }
+    );
+    Ok(!is_sale)
+}
+
+/// Returns whether moving a clock from `current` to `clock` sets back the last sale the rate decays
+/// from, lowering its fee. Clearing the clock stops the decay instead.
+fn sets_clock_back(
+    rate_info: &RateInfo,
+    block: &BlockInfo,
+    current: &SaleClock,
+    clock: &SaleClock,
+) -> bool {
+    let (_, last) = rate_info.clock_position(block, clock);
+    let (_, current_last) = rate_info.clock_position(block, current);
+    last != 0 && last < current_last
+}
+
+fn diff_sale_clock(
+    prefix: &str,
+    current: &SaleClock,
+    clock: &SaleClock,
+) -> StdResult<Vec<AuditChange>> {
+    Ok(diff_value(
+        &format!("{prefix}last_timestamp"),
+        Some(&current.last_timestamp),
+        Some(&clock.last_timestamp),
+    )?
+    .into_iter()
+    .chain(diff_value(
+        &format!("{prefix}last_height"),
+        Some(&current.last_height),
+        Some(&clock.last_height),
+    )?)
+    .collect())
+}
+
+/// Returns the sale clock the threshold decay of a rate is measured from, either its own or the
+/// shared one.
+fn rate_clock(
+    storage: &dyn Storage,
+    rate_info: &RateInfo,
+    config: &Config,
+) -> StdResult<SaleClock> {
+    match (&rate_info.clock, &rate_info.description) {
+        (Some(_), Some(description)) => Ok(SALE_CLOCKS
+            .may_load(storage, description)?
+            .unwrap_or_default()),
+        _ => Ok(config.sale_clock()),
+    }
+}
+
+/// Returns the units the shared sale clock moves in. Only thresholded rates decay, so the clock
+/// only moves in the units their thresholds are measured in and is left alone if none of them use
+/// it.
+fn shared_clock_units(config: &Config) -> Vec<ClockUnit> {
+    config
+        .rates
+        .iter()
+        .filter(|rate_info| rate_info.threshold.is_some() && rate_info.clock.is_none())
+        .map(|rate_info| rate_info.clock_unit())
+        .collect()
+}
+
+/// Returns a sale clock after a payment, none if it doesn't move. Only the units the thresholds
+/// driven by the clock decay in are moved, the others are left as they are.
+fn next_sale_clock(
+    policy: Option<&ClockResetPolicy>,
+    payment: &Coin,
+    block: &BlockInfo,
+    clock: &SaleClock,
+    units: &[ClockUnit],
+) -> Option<SaleClock> {
+    let next = |current: u64, last: u64| match policy {
+        Some(policy) => policy.next_timestamp(payment, current, last),
+        None => Some(current),
+    };
+    let next_timestamp = units
+        .contains(&ClockUnit::Seconds)
+        .then(|| next(block.time.seconds(), clock.last_timestamp))
+        .flatten();
+    let next_height = units
+        .contains(&ClockUnit::Blocks)
+        .then(|| next(block.height, clock.last_height))
+        .flatten();
+    if next_timestamp.is_none() && next_height.is_none() {
+        return None;
+    }
+    Some(SaleClock {
+        last_timestamp: next_timestamp.unwrap_or(clock.last_timestamp),
+        last_height: next_height.unwrap_or(clock.last_height),
+    })
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // New version
+    let version: Version = CONTRACT_VERSION.parse().map_err(from_semver)?;
+
+    // Old version
+    let stored = get_contract_version(deps.storage)?;
+    let storage_version: Version = stored.version.parse().map_err(from_semver)?;
+
+    let contract = ADOContract::default();
+
+    ensure!(
+        stored.contract == CONTRACT_NAME,
+        ContractError::CannotMigrate {
+            previous_contract: stored.contract,
+        }
+    );
+
+    // New version has to be newer/greater than the old version
+    ensure!(
+        storage_version < version,
+        ContractError::CannotMigrate {
+            previous_contract: stored.version,
+        }
+    );
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // Contracts from before the rate history was kept start it with their current rates, which
+    // also builds the index from recipients to rates
+    let has_history = RATE_VERSIONS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if !has_history {
+        let config = CONFIG.load(deps.storage)?;
+        save_rates(deps.storage, &env.block, &config)?;
+    }
+
+    // Update the ADOContract's version
+    contract.execute_update_version(deps)?;
+
+    Ok(Response::default())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
+    match msg {
+        QueryMsg::AndrHook(msg) => handle_andromeda_hook(deps, env, msg),
+        QueryMsg::Payments {} => encode_binary(&query_payments(deps)?),
+        QueryMsg::TransferRates {} => encode_binary(&query_transfer_rates(deps)?),
+        QueryMsg::AcceptedAssets {} => encode_binary(&query_accepted_assets(deps)?),
+        QueryMsg::DeductionCap {} => encode_binary(&query_deduction_cap(deps)?),
+        QueryMsg::CurrentRates { start_after, limit } => {
+            encode_binary(&query_current_rates(deps, env, start_after, limit)?)
+        }
+        QueryMsg::RatesAt { time, height } => encode_binary(&query_rates_at(deps, time, height)?),
+        QueryMsg::RateHistory { start_after, limit } => {
+            encode_binary(&query_rate_history(deps, start_after, limit)?)
+        }
+        QueryMsg::AuditLog { start_after, limit } => {
+            encode_binary(&query_audit_log(deps, start_after, limit)?)
+        }
+        QueryMsg::PauseStatus {} => encode_binary(&query_pause_status(deps, env)?),
+        QueryMsg::CircuitBreaker {} => encode_binary(&query_circuit_breaker(deps)?),
+        QueryMsg::HookCallers {} => encode_binary(&query_hook_callers(deps)?),
+        QueryMsg::ClockResetPolicy {} => encode_binary(&query_clock_reset_policy(deps)?),
+        QueryMsg::SaleClock { description } => encode_binary(&query_sale_clock(deps, description)?),
+        QueryMsg::DynamicRate { description } => {
+            encode_binary(&query_dynamic_rate(deps, description)?)
+        }
+        QueryMsg::Commitment { description } => {
+            encode_binary(&query_commitment(deps, description)?)
+        }
+        QueryMsg::Commitments { start_after, limit } => {
+            encode_binary(&query_commitments(deps, start_after, limit)?)
+        }
+        QueryMsg::RotationVetoPeriod {} => encode_binary(&query_rotation_veto_period(deps)?),
+        QueryMsg::PendingRotations { start_after, limit } => {
+            encode_binary(&query_pending_rotations(deps, start_after, limit)?)
+        }
+        QueryMsg::Roles { address } => encode_binary(&query_roles(deps, address)?),
+        QueryMsg::RoleHolders { start_after, limit } => {
+            encode_binary(&query_role_holders(deps, start_after, limit)?)
+        }
+        QueryMsg::Approvers {} => encode_binary(&query_approvers(deps)?),
+        QueryMsg::Proposal { proposal_id } => encode_binary(&query_proposal(deps, proposal_id)?),
+        QueryMsg::Proposals { start_after, limit } => {
+            encode_binary(&query_proposals(deps, start_after, limit)?)
+        }
+        QueryMsg::RatesForRecipient { address } => {
+            encode_binary(&query_rates_for_recipient(deps, address)?)
+        }
+        QueryMsg::Rates {
+            start_after,
+            limit,
+            filter,
+        } => encode_binary(&query_rates(deps, start_after, limit, filter)?),
+        QueryMsg::NetProceeds { amount, context }
+        | QueryMsg::TotalBuyerCost { amount, context } => {
+            encode_binary(&query_pricing(deps, env, amount, context)?)
+        }
+        QueryMsg::RequiredPayment { net, context } => {
+            encode_binary(&query_required_payment(deps, env, net, context)?)
+        }
+        _ => ADOContract::default().query(deps, env, msg),
+    }
+}
+
+fn handle_andromeda_hook(
+    deps: Deps,
+    env: Env,
+    msg: AndromedaHook,
+) -> Result<Binary, ContractError> {
+    let pause_mode = safe_mode(&deps, &env)?;
+    match msg {
+        AndromedaHook::OnFundsTransfer {
+            amount, payload, ..
+        } => match pause_mode {
+            Some(PauseMode::HaltSales) => Err(sales_halted_error()),
+            Some(PauseMode::SkipFees) => encode_binary(&OnFundsTransferResponse {
+                msgs: vec![],
+                leftover_funds: amount,
+                events: vec![],
+            }),
+            None => {
+                let context = SaleContext::from_payload(&payload);
+                encode_binary(&query_deducted_funds(deps, env, amount, context)?)
+            }
+        },
+        AndromedaHook::OnTokenTransfer { sender, .. } => match pause_mode {
+            // Only sales are halted, transfers go through without fees
+            Some(_) => Ok(encode_binary(&None::<Response>)?),
+            None => encode_binary(&query_transfer_fees(deps, sender)?),
+        },
+        _ => Ok(encode_binary(&None::<Response>)?),
+    }
+}
+
+fn sales_halted_error() -> ContractError {
+    ContractError::CustomError {
+        msg: "Sales are halted while fee collection is paused".to_string(),
+    }
+}
+
+fn query_transfer_rates(deps: Deps) -> Result<TransferRatesResponse, ContractError> {
+    let rates = TRANSFER_RATES.may_load(deps.storage)?.unwrap_or_default();
+    Ok(TransferRatesResponse { rates })
+}
+
+fn query_accepted_assets(deps: Deps) -> Result<AcceptedAssetsResponse, ContractError> {
+    Ok(AcceptedAssetsResponse {
+        accepted_assets: ACCEPTED_ASSETS.may_load(deps.storage)?,
+    })
+}
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// Returns the rates matching `filter` along with their index, starting after the index
+/// `start_after`.
+fn paginate_rates(
+    rates: Vec<RateInfo>,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+    filter: Option<RateFilter>,
+) -> Vec<(u32, RateInfo)> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map_or(0, |index| index as usize + 1);
+    rates
+        .into_iter()
+        .enumerate()
+        .skip(start)
+        .filter(|(_, rate_info)| {
+            filter
+                .as_ref()
+                .map_or(true, |filter| filter.matches(rate_info))
+        })
+        .take(limit)
+        .map(|(index, rate_info)| (index as u32, rate_info))
+        .collect()
+}
+
+fn query_rates(
+    deps: Deps,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+    filter: Option<RateFilter>,
+) -> Result<RatesResponse, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let rates = paginate_rates(config.rates, start_after, limit, filter)
+        .into_iter()
+        .map(|(index, rate)| IndexedRate { index, rate })
+        .collect();
+
+    Ok(RatesResponse { rates })
+}
+
+fn query_rates_at(
+    deps: Deps,
+    time: Option<u64>,
+    height: Option<u64>,
+) -> Result<RatesAtResponse, ContractError> {
+    // The version in effect is the last one saved at or before the given height or time
+    let (index, at) = match (time, height) {
+        (Some(time), None) => (RATE_VERSIONS_BY_TIME, time),
+        (None, Some(height)) => (RATE_VERSIONS_BY_HEIGHT, height),
+        _ => {
+            return Err(ContractError::CustomError {
+                msg: "Exactly one of time or height must be provided".to_string(),
+            })
+        }
+    };
+    let version = index
+        .range(
+            deps.storage,
+            None,
+            Some(Bound::inclusive(at)),
+            Order::Descending,
+        )
+        .next()
+        .transpose()?
+        .map(|(_, version)| RATE_VERSIONS.load(deps.storage, version))
+        .transpose()?;
+    Ok(RatesAtResponse { version })
+}
+
+fn query_rate_history(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<RateHistoryResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let versions = RATE_VERSIONS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, rate_version)| rate_version))
+        .collect::<StdResult<Vec<RateVersion>>>()?;
+
+    Ok(RateHistoryResponse { versions })
+}
+
+fn query_audit_log(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<AuditLogResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let entries = AUDIT_LOG
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, entry)| entry))
+        .collect::<StdResult<Vec<AuditEntry>>>()?;
+
+    Ok(AuditLogResponse { entries })
+}
+
+fn query_clock_reset_policy(deps: Deps) -> Result<ClockResetPolicyResponse, ContractError> {
+    let policy = CLOCK_RESET_POLICY.may_load(deps.storage)?;
+    Ok(ClockResetPolicyResponse { policy })
+}
+
+fn query_sale_clock(deps: Deps, description: String) -> Result<SaleClockResponse, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let rate_info = config
+        .rates
+        .iter()
+        .find(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Rate {description} does not exist"),
+        })?;
+    let reset_policy = match &rate_info.clock {
+        Some(clock) => clock.reset_policy.clone(),
+        None => CLOCK_RESET_POLICY.may_load(deps.storage)?,
+    };
+    let clock = rate_clock(deps.storage, rate_info, &config)?;
+    Ok(SaleClockResponse {
+        last_timestamp: clock.last_timestamp,
+        last_height: clock.last_height,
+        is_own_clock: rate_info.clock.is_some(),
+        reset_policy,
+    })
+}
+
+fn query_dynamic_rate(deps: Deps, description: String) -> Result<DynamicFeeState, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let rate_info = config
+        .rates
+        .iter()
+        .find(|rate_info| rate_info.description.as_ref() == Some(&description))
+        .ok_or_else(|| ContractError::CustomError {
+            msg: format!("Rate {description} does not exist"),
+        })?;
+    dynamic_state(deps.storage, rate_info)?.ok_or_else(|| ContractError::CustomError {
+        msg: format!("Rate {description} is not dynamic"),
+    })
+}
+
+fn query_circuit_breaker(deps: Deps) -> Result<CircuitBreakerResponse, ContractError> {
+    Ok(CircuitBreakerResponse {
+        circuit_breaker: CIRCUIT_BREAKER.may_load(deps.storage)?,
+        state: BREAKER_STATE.may_load(deps.storage)?.unwrap_or_default(),
+    })
+}
+
+fn query_hook_callers(deps: Deps) -> Result<HookCallersResponse, ContractError> {
+    Ok(HookCallersResponse {
+        callers: HOOK_CALLERS.may_load(deps.storage)?.unwrap_or_default(),
+    })
+}
+
+fn query_pause_status(deps: Deps, env: Env) -> Result<PauseStatusResponse, ContractError> {
+    let pause = active_pause(&deps, &env)?;
+    Ok(PauseStatusResponse { pause })
+}
+
+fn query_commitment(deps: Deps, description: String) -> Result<RateCommitment, ContractError> {
+    Ok(COMMITMENTS.load(deps.storage, &description)?)
+}
+
+fn query_commitments(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<CommitmentsResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let commitments = COMMITMENTS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, commitment)| commitment))
+        .collect::<StdResult<Vec<RateCommitment>>>()?;
+
+    Ok(CommitmentsResponse { commitments })
+}
+
+fn query_rotation_veto_period(deps: Deps) -> Result<RotationVetoPeriodResponse, ContractError> {
+    let period = ROTATION_VETO_PERIOD.may_load(deps.storage)?;
+    Ok(RotationVetoPeriodResponse { period })
+}
+
+fn query_pending_rotations(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<PendingRotationsResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let rotations = PENDING_ROTATIONS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, rotation)| rotation))
+        .collect::<StdResult<Vec<PendingRotation>>>()?;
+
+    Ok(PendingRotationsResponse { rotations })
+}
+
+fn query_roles(deps: Deps, address: String) -> Result<RolesResponse, ContractError> {
+    let roles = ROLES.may_load(deps.storage, &address)?;
+    Ok(RolesResponse { roles })
+}
+
+fn query_role_holders(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<RoleHoldersResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let holders = ROLES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(address, roles)| RoleHolder { address, roles }))
+        .collect::<StdResult<Vec<RoleHolder>>>()?;
+
+    Ok(RoleHoldersResponse { holders })
+}
+
+fn query_approvers(deps: Deps) -> Result<ApproversResponse, ContractError> {
+    let approver_set = APPROVERS.may_load(deps.storage)?;
+    Ok(ApproversResponse { approver_set })
+}
+
+fn query_proposal(deps: Deps, proposal_id: u64) -> Result<Proposal, ContractError> {
+    Ok(PROPOSALS.load(deps.storage, proposal_id)?)
+}
+
+fn query_proposals(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<ProposalsResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let proposals = PROPOSALS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, proposal)| proposal))
+        .collect::<StdResult<Vec<Proposal>>>()?;
+
+    Ok(ProposalsResponse { proposals })
+}
+
+fn query_rates_for_recipient(
+    deps: Deps,
+    address: String,
+) -> Result<RatesForRecipientResponse, ContractError> {
+    let rates = RECIPIENT_RATES
+        .may_load(deps.storage, &address)?
+        .unwrap_or_default();
+    Ok(RatesForRecipientResponse { rates })
+}
+
+fn query_current_rates(
+    deps: Deps,
+    env: Env,
+    start_after: Option<u32>,
+    limit: Option<u32>,
+) -> Result<CurrentRatesResponse, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let rates = paginate_rates(config.rates, start_after, limit, None)
+        .into_iter()
+        .map(|(index, rate_info)| {
+            let (rate, next_change) = match dynamic_state(deps.storage, &rate_info)? {
+                // Dynamic rates only change on the next sale
+                Some(state) => (
+                    Rate::Percent(PercentRate {
+                        percent: state.percent,
+                    }),
+                    None,
+                ),
+                None => {
+                    let clock = rate_clock(deps.storage, &rate_info, &config)?;
+                    let (current, last) = rate_info.clock_position(&env.block, &clock);
+                    rate_info.current_rate(current, last)
+                }
+            };
+            Ok(CurrentRate {
+                index,
+                description: rate_info.description,
+                is_additive: rate_info.is_additive,
+                rate,
+                next_change,
+            })
+        })
+        .collect::<StdResult<Vec<CurrentRate>>>()?;
+
+    Ok(CurrentRatesResponse { rates })
+}
+
+fn query_deduction_cap(deps: Deps) -> Result<DeductionCapResponse, ContractError> {
+    Ok(DeductionCapResponse {
+        cap: DEDUCTION_CAP.may_load(deps.storage)?,
+    })
+}
+
+//NOTE Currently set as pub for testing
+/// Generates the flat fee payments due on a token transfer. The fees are paid by the transferrer,
+/// who is expected to send them along with the transfer.
+pub fn query_transfer_fees(deps: Deps, sender: String) -> Result<Option<Response>, ContractError> {
+    let rates = TRANSFER_RATES.may_load(deps.storage)?.unwrap_or_default();
+    if rates.is_empty() {
+        return Ok(None);
+    }
+
+    let mut resp = Response::new();
+    for rate_info in rates.iter() {
+        let mut event = Event::new("transfer_fee").add_attribute("payer", sender.clone());
+        if let Some(desc) = &rate_info.description {
+            event = event.add_attribute("description", desc);
+        }
+        let (fee, is_native) = match rate_info.rate.validate(&deps.querier)? {
+            Rate::Flat(coin) => (coin, true),
+            Rate::Cw20Flat(Cw20FlatRate { address, amount }) => {
+                (coin(amount.u128(), address), false)
+            }
+            _ => return Err(ContractError::InvalidRate {}),
+        };
+        for receiver in rate_info.recipients.iter() {
+            event = event.add_attribute(
+                "payment",
+                PaymentAttribute {
+                    receiver: receiver.get_addr(),
+                    amount: fee.clone(),
+                }
+                .to_string(),
+            );
+            let msg = if is_native {
+                receiver.generate_direct_msg(&deps, vec![fee.clone()])?
+            } else {
+                receiver.generate_msg_cw20(
+                    &deps,
+                    Cw20Coin {
+                        amount: fee.amount,
+                        address: fee.denom.to_string(),
+                    },
+                )?
+            };
+            resp = resp.add_submessage(msg);
+        }
+        resp = resp.add_event(event);
+    }
+
+    Ok(Some(resp))
+}
+
+fn query_payments(deps: Deps) -> Result<PaymentsResponse, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    Ok(PaymentsResponse {
+        payments: config.rates,
+        last_timestamp: config.last_timestamp,
+        last_height: config.last_height,
+    })
+}
+
+//NOTE Currently set as pub for testing
+pub fn query_deducted_funds(
+    deps: Deps,
+    env: Env,
+    funds: Funds,
+    context: Option<SaleContext>,
+) -> Result<OnFundsTransferResponse, ContractError> {
+    let current_timestamp = env.block.time.seconds();
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    let mut events: Vec<Event> = vec![];
+    let (coin, is_native) = split_funds(funds);
+    // Reject payments in assets that haven't been approved so the sale can be blocked early
+    ensure_accepted_asset(&deps, &coin, is_native)?;
+    let mut rate_fees = evaluate_rates(&deps, &env, &coin, is_native, &context)?;
+    let capped_event = apply_deduction_cap(&deps, &coin, &mut rate_fees)?;
+    let circuit_breaker = match CIRCUIT_BREAKER.may_load(deps.storage)? {
+        Some(circuit_breaker) => {
+            // Only fees in the asset of the payment count towards the limits
+            let fees = rate_fees
+                .iter()
+                .flat_map(|rate_fee| rate_fee.payments.iter())
+                .filter(|(_, amount)| amount.denom == coin.denom)
+                .try_fold(Uint128::zero(), |total, (_, amount)| {
+                    total.checked_add(amount.amount)
+                })?;
+            Some((circuit_breaker, fees))
+        }
+        None => None,
+    };
+
+    let mut leftover_funds = vec![coin.clone()];
+    for rate_fee in rate_fees.into_iter() {
+        let mut event = rate_fee.event;
+        for (receiver, amount) in rate_fee.payments.iter() {
+            if amount.amount.is_zero() {
+                continue;
+            }
+            if rate_fee.is_deducted {
+                deduct_funds(&mut leftover_funds, amount)?;
+                event = event.add_attribute("deducted", amount.to_string());
+            }
+            event = event.add_attribute(
+                "payment",
+                PaymentAttribute {
+                    receiver: receiver.get_addr(),
+                    amount: amount.clone(),
+                }
+                .to_string(),
+            );
+            let msg = if rate_fee.is_native {
+                receiver.generate_direct_msg(&deps, vec![amount.clone()])?
+            } else {
+                receiver.generate_msg_cw20(
+                    &deps,
+                    Cw20Coin {
+                        amount: amount.amount,
+                        address: amount.denom.to_string(),
+                    },
+                )?
+            };
+            msgs.push(msg);
+        }
+        events.push(event);
+    }
+    events.extend(capped_event);
+
+    // Sales are only reported back once the marketplaces running the hook's messages are
+    // registered, as the reports would fail the sale otherwise
+    let reports_sales = HOOK_CALLERS
+        .may_load(deps.storage)?
+        .map_or(false, |callers| !callers.is_empty());
+
+    if let Some((circuit_breaker, fees)) = circuit_breaker {
+        let record_msg = SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: encode_binary(&ExecuteMsg::RecordFees {
+                payment: to_funds(coin.clone(), is_native),
+                fees,
+            })?,
+            funds: vec![],
+        });
+        let state = BREAKER_STATE.may_load(deps.storage)?.unwrap_or_default();
+        // Failing the sale would revert the record as well, so the sale exceeding the limit goes
+        // through without fees whatever the mode and the mode applies from the next sale on
+        if let Some(reason) = circuit_breaker.violation(&state, current_timestamp, &coin, fees) {
+            return Ok(OnFundsTransferResponse {
+                msgs: reports_sales.then_some(record_msg).into_iter().collect(),
+                leftover_funds: to_funds(coin, is_native),
+                events: vec![Event::new("circuit_breaker").add_attribute("tripped", reason)],
+            });
+        }
+        if reports_sales {
+            msgs.push(record_msg);
+        }
+    }
+    if !reports_sales {
+        return Ok(OnFundsTransferResponse {
+            msgs,
+            leftover_funds: to_funds(leftover_funds[0].clone(), is_native),
+            events,
+        });
+    }
+
+    // Adjust the dynamic rates to the pace of sales
+    let config = CONFIG.load(deps.storage)?;
+    if config
+        .rates
+        .iter()
+        .any(|rate_info| rate_info.dynamic.is_some())
+    {
+        msgs.push(SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: encode_binary(&ExecuteMsg::RecordSale {})?,
+            funds: vec![],
+        }));
+    }
+
+    // Move the sale clocks according to their reset policy, to the current time and height by
+    // default. The payment is reported along so that the move can be checked against the policy.
+    let shared_units = shared_clock_units(&config);
+    let policy = CLOCK_RESET_POLICY.may_load(deps.storage)?;
+    if let Some(clock) = next_sale_clock(
+        policy.as_ref(),
+        &coin,
+        &env.block,
+        &config.sale_clock(),
+        &shared_units,
+    ) {
+        msgs.push(SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
+                last_timestamp: clock.last_timestamp,
+                last_height: shared_units
+                    .contains(&ClockUnit::Blocks)
+                    .then_some(clock.last_height),
+                payment: Some(to_funds(coin.clone(), is_native)),
+            })?,
+            funds: vec![],
+        }));
+    }
+    for rate_info in config.rates.iter() {
+        if let (Some(own_clock), Some(description)) = (&rate_info.clock, &rate_info.description) {
+            let units = [rate_info.clock_unit()];
+            let current = rate_clock(deps.storage, rate_info, &config)?;
+            if let Some(clock) = next_sale_clock(
+                own_clock.reset_policy.as_ref(),
+                &coin,
+                &env.block,
+                &current,
+                &units,
+            ) {
+                msgs.push(SubMsg::new(WasmMsg::Execute {
+                    contract_addr: env.contract.address.to_string(),
+                    msg: encode_binary(&ExecuteMsg::UpdateRateTimestamp {
+                        description: description.clone(),
+                        last_timestamp: clock.last_timestamp,
+                        last_height: units
+                            .contains(&ClockUnit::Blocks)
+                            .then_some(clock.last_height),
+                        payment: Some(to_funds(coin.clone(), is_native)),
+                    })?,
+                    funds: vec![],
+                }));
+            }
+        }
+    }
+
+    Ok(OnFundsTransferResponse {
+        msgs,
+        leftover_funds: to_funds(leftover_funds[0].clone(), is_native),
+        events,
+    })
+}
+
+/// Splits `funds` into a `Coin`, whose denom is the cw20 address for cw20 funds, and whether the
+/// funds are native.
+fn split_funds(funds: Funds) -> (Coin, bool) {
+    match funds {
+        Funds::Native(coin) => (coin, true),
+        Funds::Cw20(cw20_coin) => (coin(cw20_coin.amount.u128(), cw20_coin.address), false),
+    }
+}
+
+fn to_funds(coin: Coin, is_native: bool) -> Funds {
+    if is_native {
+        Funds::Native(coin)
+    } else {
+        Funds::Cw20(Cw20Coin {
+            amount: coin.amount,
+            address: coin.denom,
+        })
+    }
+}
+
+fn ensure_accepted_asset(
+    deps: &Deps,
+    payment: &Coin,
+    is_native: bool,
+) -> Result<(), ContractError> {
+    if let Some(accepted_assets) = ACCEPTED_ASSETS.may_load(deps.storage)? {
+        ensure!(
+            accepted_assets.accepts(&payment.denom, is_native),
+            ContractError::InvalidFunds {
+                msg: format!(
+                    "Payments in {} {} are not accepted",
+                    if is_native { "native denom" } else { "cw20" },
+                    payment.denom
+                ),
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Returns the total amounts deducted from and added on top of a payment, evaluated exactly as in
+/// the funds transfer hook, including while fee collection is paused or the breaker is tripped.
+fn evaluate_payment(
+    deps: &Deps,
+    env: &Env,
+    payment: &Coin,
+    is_native: bool,
+    context: &Option<SaleContext>,
+) -> Result<(Uint128, Uint128), ContractError> {
+    match safe_mode(deps, env)? {
+        Some(PauseMode::HaltSales) => return Err(sales_halted_error()),
+        Some(PauseMode::SkipFees) => return Ok((Uint128::zero(), Uint128::zero())),
+        None => {}
+    }
+    let mut rate_fees = evaluate_rates(deps, env, payment, is_native, context)?;
+    apply_deduction_cap(deps, payment, &mut rate_fees)?;
+    Ok((
+        total_fees(&rate_fees, true)?,
+        total_fees(&rate_fees, false)?,
+    ))
+}
+
+fn pricing_response(
+    deps: &Deps,
+    env: &Env,
+    price: Coin,
+    is_native: bool,
+    context: &Option<SaleContext>,
+) -> Result<PricingResponse, ContractError> {
+    let (deducted, added) = evaluate_payment(deps, env, &price, is_native, context)?;
+    let net = Coin::new(
+        price.amount.checked_sub(deducted)?.u128(),
+        price.denom.clone(),
+    );
+    let total_buyer_cost = Coin::new(price.amount.checked_add(added)?.u128(), price.denom.clone());
+    Ok(PricingResponse {
+        price: to_funds(price, is_native),
+        net: to_funds(net, is_native),
+        total_buyer_cost: to_funds(total_buyer_cost, is_native),
+    })
+}
+
+fn query_pricing(
+    deps: Deps,
+    env: Env,
+    amount: Funds,
+    context: Option<SaleContext>,
+) -> Result<PricingResponse, ContractError> {
+    let (price, is_native) = split_funds(amount);
+    ensure_accepted_asset(&deps, &price, is_native)?;
+    pricing_response(&deps, &env, price, is_native, &context)
+}
+
+/// Finds the lowest price at which the seller nets at least `net` using a binary search.
+fn query_required_payment(
+    deps: Deps,
+    env: Env,
+    net: Funds,
+    context: Option<SaleContext>,
+) -> Result<PricingResponse, ContractError> {
+    let (net, is_native) = split_funds(net);
+    ensure_accepted_asset(&deps, &net, is_native)?;
+
+    // Whether the seller nets at least `net` at the given price
+    let reaches_net = |price: Uint128| -> Result<bool, ContractError> {
+        let (deducted, _) = evaluate_payment(
+            &deps,
+            &env,
+            &Coin::new(price.u128(), net.denom.clone()),
+            is_native,
+            &context,
+        )?;
+        Ok(price
+            .checked_sub(deducted)
+            .map_or(false, |proceeds| proceeds >= net.amount))
+    };
+
+    let mut high = std::cmp::max(net.amount, Uint128::new(1));
+    while !reaches_net(high)? {
+        high = high
+            .checked_mul(Uint128::new(2))
+            .map_err(|_| ContractError::CustomError {
+                msg: format!("No price nets {net}"),
+            })?;
+    }
+    let mut low = Uint128::zero();
+    while low < high {
+        let mid = low + (high - low) / Uint128::new(2);
+        if reaches_net(mid)? {
+            high = mid;
+        } else {
+            low = mid + Uint128::new(1);
+        }
+    }
+
+    pricing_response(
+        &deps,
+        &env,
+        Coin::new(high.u128(), net.denom),
+        is_native,
+        &context,
+    )
+}
+
+/// The payments due for a single rate, before they are made
+struct RateFees {
+    event: Event,
+    /// Whether the payments are made in a native denom rather than a cw20 token
+    is_native: bool,
+    /// Whether the payments are deducted from the funds rather than added on top of them
+    is_deducted: bool,
+    /// Whether the payments are royalties scaled down by the deduction cap
+    is_capped: bool,
+    payments: Vec<(Recipient, Coin)>,
+}
+
+/// Evaluates every configured rate against a payment, in order.
+///
+/// For cw20 payments the `denom` of `payment` is the cw20 contract address. Skipped rates are
+/// returned without any payments so that their event is kept in order.
+fn evaluate_rates(
+    deps: &Deps,
+    env: &Env,
+    payment: &Coin,
+    is_native: bool,
+    context: &Option<SaleContext>,
+) -> Result<Vec<RateFees>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let mut rate_fees: Vec<RateFees> = vec![];
+    // The amounts deducted by each rate so far, used to calculate the base of later rates
+    let mut deducted: Vec<(Option<String>, Uint128)> = vec![];
+    for rate_info in config.rates.iter() {
+        // Skip rates whose sale condition doesn't match
+        if !rate_info.applies_to(context) {
+            continue;
+        }
+        // Skip rates whose predicates don't match the payment
+        if let Some(predicate) = rate_info
+            .predicate
+            .as_ref()
+            .and_then(|predicate| predicate.failed_predicate(payment, is_native))
+        {
+            let mut event = Event::new("skipped");
+            if let Some(desc) = &rate_info.description {
+                event = event.add_attribute("description", desc);
+            }
+            rate_fees.push(RateFees {
+                event: event.add_attribute("predicate", predicate),
+                is_native,
+                is_deducted: false,
+                is_capped: false,
+                payments: vec![],
+            });
+            continue;
+        }
+        let event_name = if rate_info.is_additive {
+            "tax"
+        } else {
+            "royalty"
+        };
+        let mut event = Event::new(event_name);
+        if let Some(desc) = &rate_info.description {
+            event = event.add_attribute("description", desc);
+        }
+        let rate = match dynamic_state(deps.storage, rate_info)? {
+            Some(state) => Rate::Percent(PercentRate {
+                percent: state.percent,
+            }),
+            None => rate_info.rate.validate(&deps.querier)?,
+        };
+        // Flat fees carry their own asset which has to match the payment
+        let fee_is_native = match rate.flat_asset() {
+            Some((asset, fee_is_native)) => {
+                ensure!(
+                    asset == payment.denom && fee_is_native == is_native,
+                    ContractError::InvalidFunds {
+                        msg: format!(
+                            "Flat fee asset {} does not match payment asset {}",
+                            asset, payment.denom
+                        ),
+                    }
+                );
+                fee_is_native
+            }
+            None => is_native,
+        };
+        let base = match &rate_info.base {
+            Some(base) => {
+                let amount = base.amount(payment.amount, &deducted)?;
+                event = event
+                    .add_attribute("base", base.to_string())
+                    .add_attribute("base_amount", amount.to_string());
+                amount
+            }
+            None => payment.amount,
+        };
+        let base = Coin::new(base.u128(), payment.denom.clone());
+        let rounding = rate_info
+            .rounding
+            .as_ref()
+            .map(|rounding| rounding.mode.clone());
+        let fee = if rate_info.is_inclusive() {
+            // Inclusive taxes are carved out of the payment
+            let fee = calculate_inclusive_fee(rate, &base, rounding)?;
+            let tax_base = Coin::new(base.amount.checked_sub(fee.amount)?.u128(), base.denom);
+            event = event
+                .add_attribute("tax_base", tax_base.to_string())
+                .add_attribute("tax", fee.to_string());
+            fee
+        } else {
+            let clock = rate_clock(deps.storage, rate_info, &config)?;
+            let (current, last) = rate_info.clock_position(&env.block, &clock);
+            calculate_fee(
+                rate,
+                &base,
+                rate_info.threshold.clone(),
+                current,
+                last,
+                rounding,
+            )?
+        };
+        let (payments, dust) = rate_payments(rate_info, &fee)?;
+        if let Some(dust) = dust {
+            event = event.add_attribute("dust", dust.to_string());
+        }
+        if rate_info.is_deducted() {
+            let rate_deducted = payments
+                .iter()
+                .try_fold(Uint128::zero(), |sum, (_, amount)| {
+                    sum.checked_add(amount.amount)
+                })?;
+            deducted.push((rate_info.description.clone(), rate_deducted));
+        }
+        rate_fees.push(RateFees {
+            event,
+            is_native: fee_is_native,
+            is_deducted: rate_info.is_deducted(),
+            is_capped: !rate_info.is_additive,
+            payments,
+        });
+    }
+
+    Ok(rate_fees)
+}
+
+/// Scales every deducted payment down pro-rata if their total exceeds the deduction cap,
+/// returning a `capped` event if so.
+fn apply_deduction_cap(
+    deps: &Deps,
+    payment: &Coin,
+    rate_fees: &mut [RateFees],
+) -> Result<Option<Event>, ContractError> {
+    let cap = match DEDUCTION_CAP.may_load(deps.storage)? {
+        Some(cap) => cap,
+        None => return Ok(None),
+    };
+    let cap_amount = payment.amount * cap;
+    let total = capped_fees(rate_fees)?;
+    if total <= cap_amount {
+        return Ok(None);
+    }
+
+    for rate_fee in rate_fees.iter_mut().filter(|rate_fee| rate_fee.is_capped) {
+        for (_, amount) in rate_fee.payments.iter_mut() {
+            amount.amount = amount.amount.multiply_ratio(cap_amount, total);
+        }
+    }
+
+    Ok(Some(
+        Event::new("capped")
+            .add_attribute("cap", cap.to_string())
+            .add_attribute(
+                "deducted",
+                Coin::new(total.u128(), payment.denom.clone()).to_string(),
+            )
+            .add_attribute(
+                "capped_deducted",
+                Coin::new(capped_fees(rate_fees)?.u128(), payment.denom.clone()).to_string(),
+            ),
+    ))
+}
+
+/// Returns the total amount of the royalties subject to the deduction cap.
+fn capped_fees(rate_fees: &[RateFees]) -> Result<Uint128, ContractError> {
+    let mut total = Uint128::zero();
+    for rate_fee in rate_fees.iter().filter(|rate_fee| rate_fee.is_capped) {
+        for (_, amount) in rate_fee.payments.iter() {
+            total = total.checked_add(amount.amount)?;
+        }
+    }
+    Ok(total)
+}
+
+/// Returns the total amount either deducted from the funds or added on top of them by the given
+/// rates.
+fn total_fees(rate_fees: &[RateFees], is_deducted: bool) -> Result<Uint128, ContractError> {
+    let mut total = Uint128::zero();
+    for rate_fee in rate_fees
+        .iter()
+        .filter(|rate_fee| rate_fee.is_deducted == is_deducted)
+    {
+        for (_, amount) in rate_fee.payments.iter() {
+            total = total.checked_add(amount.amount)?;
+        }
+    }
+    Ok(total)
+}
+
+/// Returns the payment due to each recipient of a rate along with any dust left after splitting.
+///
+/// Without shares every recipient is paid the full fee. With shares the fee is split between the
+/// recipients and the dust is paid to the dust recipient if one is set, otherwise it is left
+/// with the seller.
+fn rate_payments(
+    rate_info: &RateInfo,
+    fee: &Coin,
+) -> Result<(Vec<(Recipient, Coin)>, Option<Coin>), ContractError> {
+    let shares = match &rate_info.shares {
+        None => {
+            let payments = rate_info
+                .recipients
+                .iter()
+                .map(|receiver| (receiver.clone(), fee.clone()))
+                .collect();
+            return Ok((payments, None));
+        }
+        Some(shares) => shares,
+    };
+
+    let mode = rate_info
+        .rounding
+        .as_ref()
+        .map_or(RoundingMode::Down, |rounding| rounding.mode.clone());
+    let (amounts, dust) = split_fee(fee.amount, shares, &mode)?;
+    let mut payments: Vec<(Recipient, Coin)> = rate_info
+        .recipients
+        .iter()
+        .zip(amounts)
+        .map(|(receiver, amount)| (receiver.clone(), coin(amount.u128(), fee.denom.clone())))
+        .collect();
+    if dust.is_zero() {
+        return Ok((payments, None));
+    }
+
+    let dust = coin(dust.u128(), fee.denom.clone());
+    if let Some(dust_recipient) = rate_info
+        .rounding
+        .as_ref()
+        .and_then(|rounding| rounding.dust_recipient.clone())
+    {
+        payments.push((dust_recipient, dust.clone()));
+    }
+    Ok((payments, Some(dust)))
 }
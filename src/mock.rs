@@ -17,6 +17,7 @@ pub fn mock_rates_instantiate_msg(
 ) -> InstantiateMsg {
     InstantiateMsg {
         rates,
+        transfer_rates: None,
         kernel_address: kernel_address.into(),
         owner,
     }
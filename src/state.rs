@@ -1,11 +1,167 @@
-use andromeda_modules::rates::RateInfo;
+use andromeda_modules::rates::{
+    AcceptedAssets, ApproverSet, AuditEntry, BreakerState, CircuitBreaker, ClockResetPolicy,
+    DynamicFeeState, Pause, PendingRotation, Proposal, RateCommitment, RateInfo, RateVersion,
+    RecipientRate, Roles, SaleClock,
+};
 use cosmwasm_schema::cw_serde;
-use cw_storage_plus::Item;
+use cosmwasm_std::{BlockInfo, Decimal, Order, StdResult, Storage};
+use cw_storage_plus::{Item, Map};
 
 pub const CONFIG: Item<Config> = Item::new("config");
+/// Flat fees charged on NFT transfers made outside a sale
+pub const TRANSFER_RATES: Item<Vec<RateInfo>> = Item::new("transfer_rates");
+/// The assets payments can be made in, every asset is accepted if unset
+pub const ACCEPTED_ASSETS: Item<AcceptedAssets> = Item::new("accepted_assets");
+/// The maximum share of a payment that can be deducted by the rates
+pub const DEDUCTION_CAP: Item<Decimal> = Item::new("deduction_cap");
+/// The rates paying each recipient, keyed by recipient address or AMP path
+pub const RECIPIENT_RATES: Map<&str, Vec<RecipientRate>> = Map::new("recipient_rates");
+/// Every version of the rates, keyed by version
+pub const RATE_VERSIONS: Map<u64, RateVersion> = Map::new("rate_versions");
+/// The last version saved at each block height, to find the version in effect at a height
+pub const RATE_VERSIONS_BY_HEIGHT: Map<u64, u64> = Map::new("rate_versions_by_height");
+/// The last version saved at each block time in seconds, to find the version in effect at a time
+pub const RATE_VERSIONS_BY_TIME: Map<u64, u64> = Map::new("rate_versions_by_time");
+/// The administrative changes made to the contract, keyed by entry id
+pub const AUDIT_LOG: Map<u64, AuditEntry> = Map::new("audit_log");
+/// The approvers rate changes have to be proposed to, the owner changes rates directly if unset
+pub const APPROVERS: Item<ApproverSet> = Item::new("approvers");
+pub const PROPOSALS: Map<u64, Proposal> = Map::new("proposals");
+/// The roles delegated to each address
+pub const ROLES: Map<&str, Roles> = Map::new("roles");
+/// How long in seconds the owner can veto recipient rotations, they are applied immediately if unset
+pub const ROTATION_VETO_PERIOD: Item<u64> = Item::new("rotation_veto_period");
+pub const PENDING_ROTATIONS: Map<u64, PendingRotation> = Map::new("pending_rotations");
+/// Rotations are removed once applied or vetoed, so their ids are counted separately
+pub const NEXT_ROTATION_ID: Item<u64> = Item::new("next_rotation_id");
+/// The commitments made to rates, keyed by rate description
+pub const COMMITMENTS: Map<&str, RateCommitment> = Map::new("commitments");
+/// Fee collection is paused while set and active
+pub const PAUSE: Item<Pause> = Item::new("pause");
+pub const CIRCUIT_BREAKER: Item<CircuitBreaker> = Item::new("circuit_breaker");
+/// The fees tracked by the circuit breaker and whether it has tripped
+pub const BREAKER_STATE: Item<BreakerState> = Item::new("breaker_state");
+/// The addresses allowed to report sales, sales can't be recorded if unset
+pub const HOOK_CALLERS: Item<Vec<String>> = Item::new("hook_callers");
+/// How sales move the sale clock, every sale resets it if unset
+pub const CLOCK_RESET_POLICY: Item<ClockResetPolicy> = Item::new("clock_reset_policy");
+/// The adjusted value of each dynamic rate, keyed by rate description
+pub const DYNAMIC_FEES: Map<&str, DynamicFeeState> = Map::new("dynamic_fees");
+/// The sale clock of each rate with a clock of its own, keyed by rate description
+pub const SALE_CLOCKS: Map<&str, SaleClock> = Map::new("sale_clocks");
 
 #[cw_serde]
 pub struct Config {
     pub rates: Vec<RateInfo>,
     pub last_timestamp: u64,
+    /// The block height of the last sale, kept alongside `last_timestamp` for thresholds measured
+    /// in blocks
+    #[serde(default)]
+    pub last_height: u64,
+}
+
+impl Config {
+    /// Returns the sale clock shared by the rates without a clock of their own.
+    pub fn sale_clock(&self) -> SaleClock {
+        SaleClock {
+            last_timestamp: self.last_timestamp,
+            last_height: self.last_height,
+        }
+    }
+}
+
+/// Saves the config, snapshots the rates as a new version and rebuilds the index from
+/// recipients to the rates paying them.
+///
+/// Every change to the rates has to be saved through here to keep the history and index consistent.
+pub fn save_rates(storage: &mut dyn Storage, block: &BlockInfo, config: &Config) -> StdResult<()> {
+    CONFIG.save(storage, config)?;
+
+    let version = RATE_VERSIONS
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |version| version + 1);
+    RATE_VERSIONS.save(
+        storage,
+        version,
+        &RateVersion {
+            version,
+            height: block.height,
+            time: block.time.seconds(),
+            rates: config.rates.clone(),
+        },
+    )?;
+    RATE_VERSIONS_BY_HEIGHT.save(storage, block.height, &version)?;
+    RATE_VERSIONS_BY_TIME.save(storage, block.time.seconds(), &version)?;
+
+    // Rates that are no longer dynamic start over if they are made dynamic again
+    let dynamic_rates = DYNAMIC_FEES
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<String>>>()?;
+    for description in dynamic_rates.iter() {
+        let is_dynamic = config.rates.iter().any(|rate_info| {
+            rate_info.dynamic.is_some() && rate_info.description.as_ref() == Some(description)
+        });
+        if !is_dynamic {
+            DYNAMIC_FEES.remove(storage, description);
+        }
+    }
+
+    // Rates that no longer have a clock of their own start a new one if they are given one again
+    let clocks = SALE_CLOCKS
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<String>>>()?;
+    for description in clocks.iter() {
+        let has_clock = config.rates.iter().any(|rate_info| {
+            rate_info.clock.is_some() && rate_info.description.as_ref() == Some(description)
+        });
+        if !has_clock {
+            SALE_CLOCKS.remove(storage, description);
+        }
+    }
+
+    let recipients = RECIPIENT_RATES
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<String>>>()?;
+    for recipient in recipients.iter() {
+        RECIPIENT_RATES.remove(storage, recipient);
+    }
+
+    for (index, rate_info) in config.rates.iter().enumerate() {
+        let mut entries: Vec<(String, RecipientRate)> = rate_info
+            .recipients
+            .iter()
+            .enumerate()
+            .map(|(i, receiver)| {
+                let share = rate_info
+                    .shares
+                    .as_ref()
+                    .and_then(|shares| shares.get(i).cloned());
+                (
+                    receiver.get_addr(),
+                    RecipientRate::new(index as u32, rate_info, share, false),
+                )
+            })
+            .collect();
+        if let Some(dust_recipient) = rate_info
+            .rounding
+            .as_ref()
+            .and_then(|rounding| rounding.dust_recipient.as_ref())
+        {
+            entries.push((
+                dust_recipient.get_addr(),
+                RecipientRate::new(index as u32, rate_info, None, true),
+            ));
+        }
+
+        for (recipient, entry) in entries.into_iter() {
+            RECIPIENT_RATES.update(storage, &recipient, |rates| -> StdResult<_> {
+                let mut rates = rates.unwrap_or_default();
+                rates.push(entry);
+                Ok(rates)
+            })?;
+        }
+    }
+    Ok(())
 }
@@ -1,22 +1,73 @@
-use crate::contract::{execute, instantiate, query, query_deducted_funds};
-use crate::state::CONFIG;
+use crate::contract::{
+    execute, instantiate, migrate, query, query_deducted_funds, query_transfer_fees,
+};
+use crate::state::{
+    CONFIG, RATE_VERSIONS, RATE_VERSIONS_BY_HEIGHT, RATE_VERSIONS_BY_TIME, RECIPIENT_RATES,
+    TRANSFER_RATES,
+};
 use crate::testing::mock_querier::{
     mock_dependencies_custom, MOCK_KERNEL_CONTRACT, MOCK_OWNER, MOCK_RECIPIENT1, MOCK_RECIPIENT2,
 };
-use andromeda_modules::rates::{ExecuteMsg, InstantiateMsg, QueryMsg, RateInfo, Thredshold};
-use andromeda_modules::rates::{PaymentsResponse, Rate};
-use andromeda_std::ado_base::hooks::OnFundsTransferResponse;
+use andromeda_modules::rates::{
+    AcceptedAssets, ApproverSet, AssetKind, AuditChange, AuditEntry, AuditLogResponse,
+    BreakerState, CircuitBreaker, CircuitBreakerResponse, ClockResetPolicy, ClockUnit,
+    CommitmentsResponse, CurrentRate, CurrentRatesResponse, Cw20FlatRate, DeductionCapResponse,
+    DynamicFee, DynamicFeeState, HookCallersResponse, Pause, PauseMode, PauseStatusResponse,
+    PaymentsResponse, PendingRotationsResponse, PricingResponse, Proposal, ProposalAction,
+    ProposalsResponse, Rate, RateBase, RateClock, RateCommitment, RateFilter, RateHistoryResponse,
+    RatePredicate, RateVersion, RatesAtResponse, RatesForRecipientResponse, RatesResponse,
+    RecipientRate, RoleHoldersResponse, Roles, RolesResponse, Rounding, RoundingMode,
+    SaleClockResponse, SaleCondition, SaleContext, SaleType,
+};
+use andromeda_modules::rates::{
+    ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, RateInfo, Thredshold,
+};
+use andromeda_std::ado_base::hooks::{AndromedaHook, OnFundsTransferResponse};
 use andromeda_std::common::Funds;
+use andromeda_std::error::ContractError;
 use andromeda_std::{amp::recipient::Recipient, common::encode_binary};
 
-use cosmwasm_std::{attr, Decimal, Event};
+use cosmwasm_std::{attr, from_binary, to_vec, Binary, Decimal, Deps, DepsMut, Env, Event};
 use cosmwasm_std::{
     coin, coins,
     testing::{mock_env, mock_info},
     BankMsg, Coin, CosmosMsg, Response, SubMsg, Uint128, WasmMsg,
 };
+use cw2::set_contract_version;
 use cw20::{Cw20Coin, Cw20ExecuteMsg};
 
+/// Returns a rate with every optional setting unset.
+fn rate_info(
+    rate: Rate,
+    is_additive: bool,
+    description: Option<String>,
+    recipients: Vec<Recipient>,
+) -> RateInfo {
+    RateInfo {
+        rate,
+        is_additive,
+        description,
+        recipients,
+        threshold: None,
+        condition: None,
+        predicate: None,
+        rounding: None,
+        shares: None,
+        base: None,
+        inclusive: None,
+        dynamic: None,
+        clock: None,
+    }
+}
+
+/// Registers the marketplace running the hook's messages so that the hook reports sales.
+fn register_hook_caller(deps: DepsMut, env: &Env) {
+    let msg = ExecuteMsg::UpdateHookCallers {
+        callers: vec!["marketplace".to_string()],
+    };
+    let _res = execute(deps, env.clone(), mock_info(MOCK_OWNER, &[]), msg).unwrap();
+}
+
 #[test]
 fn test_instantiate_query() {
     let mut deps = mock_dependencies_custom(&[]);
@@ -24,26 +75,25 @@ fn test_instantiate_query() {
     let owner = "owner";
     let info = mock_info(owner, &[]);
     let rates = vec![
-        RateInfo {
-            rate: Rate::from(Decimal::percent(10)),
-            is_additive: true,
-            description: Some("desc1".to_string()),
-            recipients: vec![Recipient::new("", None)],
-            threshold: None,
-        },
-        RateInfo {
-            rate: Rate::Flat(Coin {
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            true,
+            Some("desc1".to_string()),
+            vec![Recipient::new("", None)],
+        ),
+        rate_info(
+            Rate::Flat(Coin {
                 amount: Uint128::from(10u128),
                 denom: "uusd".to_string(),
             }),
-            is_additive: false,
-            description: Some("desc2".to_string()),
-            recipients: vec![Recipient::new("", None)],
-            threshold: None,
-        },
+            false,
+            Some("desc2".to_string()),
+            vec![Recipient::new("", None)],
+        ),
     ];
     let msg = InstantiateMsg {
         rates: rates.clone(),
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
@@ -56,7 +106,8 @@ fn test_instantiate_query() {
         payments,
         encode_binary(&PaymentsResponse {
             payments: rates,
-            last_timestamp: 0
+            last_timestamp: 0,
+            last_height: 0,
         })
         .unwrap()
     );
@@ -66,6 +117,36 @@ fn test_instantiate_query() {
     //assert_eq!(payments, true);
 }
 
+#[test]
+fn test_instantiate_duplicate_description() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("royalty".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![
+            rate.clone(),
+            RateInfo {
+                recipients: vec![Recipient::from_string(MOCK_RECIPIENT2)],
+                ..rate
+            },
+        ],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let err = instantiate(deps.as_mut(), mock_env(), mock_info(MOCK_OWNER, &[]), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Rate description royalty is used more than once".to_string()
+        },
+        err
+    );
+}
+
 #[test]
 fn test_andr_receive() {
     let mut deps = mock_dependencies_custom(&[]);
@@ -73,26 +154,25 @@ fn test_andr_receive() {
     let owner = "owner";
     let info = mock_info(owner, &[]);
     let rates = vec![
-        RateInfo {
-            rate: Rate::from(Decimal::percent(10)),
-            is_additive: true,
-            description: Some("desc1".to_string()),
-            recipients: vec![Recipient::new("", None)],
-            threshold: None,
-        },
-        RateInfo {
-            rate: Rate::Flat(Coin {
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            true,
+            Some("desc1".to_string()),
+            vec![Recipient::new("", None)],
+        ),
+        rate_info(
+            Rate::Flat(Coin {
                 amount: Uint128::from(10u128),
                 denom: "uusd".to_string(),
             }),
-            is_additive: false,
-            description: Some("desc2".to_string()),
-            recipients: vec![Recipient::new("", None)],
-            threshold: None,
-        },
+            false,
+            Some("desc2".to_string()),
+            vec![Recipient::new("", None)],
+        ),
     ];
     let msg = InstantiateMsg {
         rates: rates.clone(),
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
@@ -104,7 +184,15 @@ fn test_andr_receive() {
 
     let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
     assert_eq!(
-        Response::new().add_attributes(vec![attr("action", "update_rates")]),
+        Response::new()
+            .add_attributes(vec![attr("action", "update_rates")])
+            .add_event(
+                Event::new("audit")
+                    .add_attribute("id", "0")
+                    .add_attribute("actor", owner)
+                    .add_attribute("time", env.block.time.seconds().to_string())
+                    .add_attribute("action", "update_rates")
+            ),
         res
     );
 }
@@ -116,26 +204,25 @@ fn test_update_sale_timestamp() {
     let owner = "owner";
     let info = mock_info(owner, &[]);
     let rates = vec![
-        RateInfo {
-            rate: Rate::from(Decimal::percent(10)),
-            is_additive: true,
-            description: Some("desc1".to_string()),
-            recipients: vec![Recipient::new("", None)],
-            threshold: None,
-        },
-        RateInfo {
-            rate: Rate::Flat(Coin {
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            true,
+            Some("desc1".to_string()),
+            vec![Recipient::new("", None)],
+        ),
+        rate_info(
+            Rate::Flat(Coin {
                 amount: Uint128::from(10u128),
                 denom: "uusd".to_string(),
             }),
-            is_additive: false,
-            description: Some("desc2".to_string()),
-            recipients: vec![Recipient::new("", None)],
-            threshold: None,
-        },
+            false,
+            Some("desc2".to_string()),
+            vec![Recipient::new("", None)],
+        ),
     ];
     let msg = InstantiateMsg {
         rates: rates.clone(),
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
@@ -144,20 +231,26 @@ fn test_update_sale_timestamp() {
     let cur_timestamp = env.block.time.seconds();
     let msg = ExecuteMsg::UpdateSaleTimestamp {
         last_timestamp: cur_timestamp,
+        last_height: None,
+        payment: None,
     };
 
     let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
     assert_eq!(
-        Response::new().add_attributes(vec![attr("action", "update_sale_timestamp")]),
-        res
+        vec![attr("action", "update_sale_timestamp")],
+        res.attributes
     );
+    // Moves made by the owner are audited
+    assert_eq!(1, res.events.len());
+    assert_eq!("audit", res.events[0].ty);
 
     let payments = query(deps.as_ref(), env.clone(), QueryMsg::Payments {}).unwrap();
     assert_eq!(
         payments,
         encode_binary(&PaymentsResponse {
             payments: rates.clone(),
-            last_timestamp: cur_timestamp
+            last_timestamp: cur_timestamp,
+            last_height: 0,
         })
         .unwrap()
     );
@@ -168,34 +261,37 @@ fn test_query_deducted_funds_native() {
     let env = mock_env();
     let info = mock_info(MOCK_OWNER, &[]);
     let rates = vec![
-        RateInfo {
-            rate: Rate::Flat(Coin {
+        rate_info(
+            Rate::Flat(Coin {
                 amount: Uint128::from(20u128),
                 denom: "uusd".to_string(),
             }),
-            is_additive: true,
-            description: Some("desc2".to_string()),
-            recipients: vec![Recipient::from_string(MOCK_RECIPIENT1)],
-            threshold: None,
-        },
-        RateInfo {
-            rate: Rate::from(Decimal::percent(10)),
-            is_additive: false,
-            description: Some("desc1".to_string()),
-            recipients: vec![Recipient::from_string(MOCK_RECIPIENT2)],
-            threshold: None,
-        },
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        ),
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        ),
     ];
     let msg = InstantiateMsg {
         rates,
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
     let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
 
-    let cur_timestamp = env.block.time.seconds();
-    let res =
-        query_deducted_funds(deps.as_ref(), env.clone(), Funds::Native(coin(100, "uusd"))).unwrap();
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
 
     let expected_msgs: Vec<SubMsg> = vec![
         SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
@@ -206,14 +302,6 @@ fn test_query_deducted_funds_native() {
             to_address: MOCK_RECIPIENT2.into(),
             amount: coins(10, "uusd"),
         })),
-        SubMsg::new(WasmMsg::Execute {
-            contract_addr: env.contract.address.to_string(),
-            msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
-                last_timestamp: cur_timestamp,
-            })
-            .unwrap(),
-            funds: vec![],
-        }),
     ];
 
     assert_eq!(
@@ -244,32 +332,30 @@ fn test_query_deducted_funds_cw20() {
     let info = mock_info(owner, &[]);
     let cw20_address = "address";
     let rates = vec![
-        RateInfo {
-            rate: Rate::Flat(Coin {
+        rate_info(
+            Rate::Cw20Flat(Cw20FlatRate {
+                address: cw20_address.to_string(),
                 amount: Uint128::from(20u128),
-                denom: cw20_address.to_string(),
             }),
-            is_additive: true,
-            description: Some("desc2".to_string()),
-            recipients: vec![Recipient::new(MOCK_RECIPIENT1, None)],
-            threshold: None,
-        },
-        RateInfo {
-            rate: Rate::from(Decimal::percent(10)),
-            is_additive: false,
-            description: Some("desc1".to_string()),
-            recipients: vec![Recipient::new(MOCK_RECIPIENT2, None)],
-            threshold: None,
-        },
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::new(MOCK_RECIPIENT1, None)],
+        ),
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![Recipient::new(MOCK_RECIPIENT2, None)],
+        ),
     ];
     let msg = InstantiateMsg {
         rates: rates.clone(),
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
     let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
 
-    let cur_timestamp = env.block.time.seconds();
     let res: OnFundsTransferResponse = query_deducted_funds(
         deps.as_ref(),
         env.clone(),
@@ -277,6 +363,7 @@ fn test_query_deducted_funds_cw20() {
             amount: 100u128.into(),
             address: "address".into(),
         }),
+        None,
     )
     .unwrap();
 
@@ -299,14 +386,6 @@ fn test_query_deducted_funds_cw20() {
             .unwrap(),
             funds: vec![],
         }),
-        SubMsg::new(WasmMsg::Execute {
-            contract_addr: env.contract.address.to_string(),
-            msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
-                last_timestamp: cur_timestamp,
-            })
-            .unwrap(),
-            funds: vec![],
-        }),
     ];
     assert_eq!(
         OnFundsTransferResponse {
@@ -336,25 +415,30 @@ fn test_query_deducted_funds_threshold_fiat() {
     let env = mock_env();
     let info = mock_info(MOCK_OWNER, &[]);
     let rates = vec![RateInfo {
-        rate: Rate::Flat(Coin {
-            amount: Uint128::from(20u128),
-            denom: "uusd".to_string(),
-        }),
-        is_additive: true,
-        description: Some("desc2".to_string()),
-        recipients: vec![Recipient::from_string(MOCK_RECIPIENT1)],
         threshold: Some(Thredshold {
             unit: 2,
             duration: 60,
             value: 5,
+            duration_unit: None,
         }),
+        ..rate_info(
+            Rate::Flat(Coin {
+                amount: Uint128::from(20u128),
+                denom: "uusd".to_string(),
+            }),
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
     }];
     let msg = InstantiateMsg {
         rates,
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
     let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+    register_hook_caller(deps.as_mut(), &env);
 
     // Update last_sale_timestamp
     let cur_timestamp = env.block.time.seconds();
@@ -362,8 +446,13 @@ fn test_query_deducted_funds_threshold_fiat() {
     config.last_timestamp = cur_timestamp - 300;
     CONFIG.save(deps.as_mut().storage, &config).unwrap();
 
-    let res =
-        query_deducted_funds(deps.as_ref(), env.clone(), Funds::Native(coin(100, "uusd"))).unwrap();
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
 
     // Should be get 10 uusd fee => (20 - (300 / 60) * 2)
     let expected_msgs: Vec<SubMsg> = vec![
@@ -375,6 +464,8 @@ fn test_query_deducted_funds_threshold_fiat() {
             contract_addr: env.contract.address.to_string(),
             msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
                 last_timestamp: cur_timestamp,
+                last_height: None,
+                payment: Some(Funds::Native(coin(100, "uusd"))),
             })
             .unwrap(),
             funds: vec![],
@@ -399,25 +490,30 @@ fn test_query_deducted_funds_threshold_fiat_limited() {
     let env = mock_env();
     let info = mock_info(MOCK_OWNER, &[]);
     let rates = vec![RateInfo {
-        rate: Rate::Flat(Coin {
-            amount: Uint128::from(20u128),
-            denom: "uusd".to_string(),
-        }),
-        is_additive: true,
-        description: Some("desc2".to_string()),
-        recipients: vec![Recipient::from_string(MOCK_RECIPIENT1)],
         threshold: Some(Thredshold {
             unit: 2,
             duration: 60,
             value: 5,
+            duration_unit: None,
         }),
+        ..rate_info(
+            Rate::Flat(Coin {
+                amount: Uint128::from(20u128),
+                denom: "uusd".to_string(),
+            }),
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
     }];
     let msg = InstantiateMsg {
         rates,
+        transfer_rates: None,
         kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
         owner: None,
     };
     let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+    register_hook_caller(deps.as_mut(), &env);
 
     // Update last_sale_timestamp
     let cur_timestamp = env.block.time.seconds();
@@ -425,8 +521,13 @@ fn test_query_deducted_funds_threshold_fiat_limited() {
     config.last_timestamp = cur_timestamp - 600;
     CONFIG.save(deps.as_mut().storage, &config).unwrap();
 
-    let res =
-        query_deducted_funds(deps.as_ref(), env.clone(), Funds::Native(coin(100, "uusd"))).unwrap();
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
 
     // Should be get 5 uusd fee => Math.max((20 - (600 / 60) * 2), 5)
     let expected_msgs: Vec<SubMsg> = vec![
@@ -438,6 +539,8 @@ fn test_query_deducted_funds_threshold_fiat_limited() {
             contract_addr: env.contract.address.to_string(),
             msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
                 last_timestamp: cur_timestamp,
+                last_height: None,
+                payment: Some(Funds::Native(coin(100, "uusd"))),
             })
             .unwrap(),
             funds: vec![],
@@ -455,3 +558,2754 @@ fn test_query_deducted_funds_threshold_fiat_limited() {
         res
     );
 }
+
+#[test]
+fn test_threshold_zero_duration() {
+    let mut deps = mock_dependencies_custom(&[]);
+    // Thresholds have to decay over a duration
+    let rate = RateInfo {
+        threshold: Some(Thredshold {
+            unit: 2,
+            duration: 0,
+            value: 5,
+            duration_unit: None,
+        }),
+        ..rate_info(
+            Rate::Flat(coin(20, "uusd")),
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
+    };
+    let msg = InstantiateMsg {
+        rates: vec![rate],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let err = instantiate(deps.as_mut(), mock_env(), mock_info(MOCK_OWNER, &[]), msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+}
+
+/// Instantiates a flat fee of 20 decaying by 2 every minute down to 5, with the last sale 5
+/// minutes ago so that the fee is down to 10, and sets the clock reset policy.
+fn instantiate_decayed_threshold(deps: DepsMut, env: &Env, policy: ClockResetPolicy) -> u64 {
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![RateInfo {
+        threshold: Some(Thredshold {
+            unit: 2,
+            duration: 60,
+            value: 5,
+            duration_unit: None,
+        }),
+        ..rate_info(
+            Rate::Flat(coin(20, "uusd")),
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
+    }];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.branch(), env.clone(), info.clone(), msg).unwrap();
+    register_hook_caller(deps.branch(), env);
+
+    let last_timestamp = env.block.time.seconds() - 300;
+    let mut config = CONFIG.load(deps.storage).unwrap();
+    config.last_timestamp = last_timestamp;
+    CONFIG.save(deps.storage, &config).unwrap();
+
+    let msg = ExecuteMsg::UpdateClockResetPolicy {
+        policy: Some(policy),
+    };
+    let _res = execute(deps, env.clone(), info, msg).unwrap();
+    last_timestamp
+}
+
+fn sale_timestamp_msg(env: &Env, last_timestamp: u64, amount: u128) -> SubMsg {
+    SubMsg::new(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: encode_binary(&ExecuteMsg::UpdateSaleTimestamp {
+            last_timestamp,
+            last_height: None,
+            payment: Some(Funds::Native(coin(amount, "uusd"))),
+        })
+        .unwrap(),
+        funds: vec![],
+    })
+}
+
+#[test]
+fn test_query_deducted_funds_threshold_min_amount_reset() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let policy = ClockResetPolicy::MinAmount {
+        min_amounts: vec![coin(1000, "uusd")],
+    };
+    let _last_timestamp = instantiate_decayed_threshold(deps.as_mut(), &env, policy);
+
+    // A dust sale pays the decayed fee without resetting the clock
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT1.into(),
+            amount: coins(10, "uusd"),
+        }))],
+        res.msgs
+    );
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(1000, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        sale_timestamp_msg(&env, env.block.time.seconds(), 1000),
+        res.msgs[1]
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_threshold_proportional_reset() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let policy = ClockResetPolicy::Proportional {
+        full_reset_amounts: vec![coin(1000, "uusd")],
+    };
+    let last_timestamp = instantiate_decayed_threshold(deps.as_mut(), &env, policy);
+
+    // A sale of a fifth of the full reset amount recovers a fifth of the 300 seconds elapsed
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(200, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        sale_timestamp_msg(&env, last_timestamp + 60, 200),
+        res.msgs[1]
+    );
+
+    // Registered hook callers can only move the clock as far as the reported sale does
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: env.block.time.seconds(),
+        last_height: None,
+        payment: Some(Funds::Native(coin(200, "uusd"))),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        msg,
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: last_timestamp + 60,
+        last_height: None,
+        payment: Some(Funds::Native(coin(200, "uusd"))),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        msg,
+    )
+    .unwrap();
+
+    // Should be get 12 uusd fee => (20 - (240 / 60) * 2)
+    let res =
+        query_deducted_funds(deps.as_ref(), env, Funds::Native(coin(100, "uusd")), None).unwrap();
+    assert_eq!(
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT1.into(),
+            amount: coins(12, "uusd"),
+        })),
+        res.msgs[0]
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_threshold_gradual_reset() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let policy = ClockResetPolicy::Gradual { max_recovery: 120 };
+    let last_timestamp = instantiate_decayed_threshold(deps.as_mut(), &env, policy);
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        sale_timestamp_msg(&env, last_timestamp + 120, 100),
+        res.msgs[1]
+    );
+
+    // Moving the clock back is a reset only the owner or an operator can make
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: last_timestamp - 1,
+        last_height: None,
+        payment: None,
+    };
+    let err = execute(deps.as_mut(), env, mock_info("marketplace", &[]), msg).unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+}
+
+#[test]
+fn test_query_deducted_funds_rate_clock() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let policy = ClockResetPolicy::Gradual { max_recovery: 120 };
+    let last_timestamp = instantiate_decayed_threshold(deps.as_mut(), &env, policy);
+
+    // A thresholded rate added with a clock of its own doesn't inherit the decayed shared clock
+    let mut rates = CONFIG.load(deps.as_ref().storage).unwrap().rates;
+    let mut rate = RateInfo {
+        description: Some("desc3".to_string()),
+        recipients: vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        clock: Some(RateClock { reset_policy: None }),
+        ..rates[0].clone()
+    };
+    rates.push(rate.clone());
+    let msg = ExecuteMsg::UpdateRates { rates };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: MOCK_RECIPIENT1.into(),
+                amount: coins(10, "uusd"),
+            })),
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: MOCK_RECIPIENT2.into(),
+                amount: coins(20, "uusd"),
+            })),
+            sale_timestamp_msg(&env, last_timestamp + 120, 100),
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: env.contract.address.to_string(),
+                msg: encode_binary(&ExecuteMsg::UpdateRateTimestamp {
+                    description: "desc3".to_string(),
+                    last_timestamp: env.block.time.seconds(),
+                    last_height: None,
+                    payment: Some(Funds::Native(coin(100, "uusd"))),
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+        ],
+        res.msgs
+    );
+
+    let msg = ExecuteMsg::UpdateRateTimestamp {
+        description: "desc3".to_string(),
+        last_timestamp: env.block.time.seconds() - 120,
+        last_height: None,
+        payment: None,
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let query_msg = QueryMsg::SaleClock {
+        description: "desc3".to_string(),
+    };
+    let res: SaleClockResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), query_msg.clone()).unwrap()).unwrap();
+    assert_eq!(
+        SaleClockResponse {
+            last_timestamp: env.block.time.seconds() - 120,
+            last_height: 0,
+            is_own_clock: true,
+            reset_policy: None,
+        },
+        res
+    );
+
+    // Should be get 16 uusd fee => (20 - (120 / 60) * 2)
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT2.into(),
+            amount: coins(16, "uusd"),
+        })),
+        res.msgs[1]
+    );
+
+    // Only the owner or an operator can reset a rate's clock
+    let msg = ExecuteMsg::UpdateRateTimestamp {
+        description: "desc3".to_string(),
+        last_timestamp: 0,
+        last_height: None,
+        payment: None,
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let res: SaleClockResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), query_msg).unwrap()).unwrap();
+    assert_eq!(0, res.last_timestamp);
+
+    // Rates without thresholds have no clock to keep
+    rate.threshold = None;
+    let msg = ExecuteMsg::UpdateRates { rates: vec![rate] };
+    let err = execute(deps.as_mut(), env, info, msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+}
+
+#[test]
+fn test_query_deducted_funds_threshold_blocks() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let mut env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    // A flat fee of 20 decaying by 2 every 10 blocks down to 5
+    let rates = vec![RateInfo {
+        threshold: Some(Thredshold {
+            unit: 2,
+            duration: 10,
+            value: 5,
+            duration_unit: Some(ClockUnit::Blocks),
+        }),
+        ..rate_info(
+            Rate::Flat(coin(20, "uusd")),
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
+    }];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+    register_hook_caller(deps.as_mut(), &env);
+
+    // The last sale was 50 blocks ago, however long they took
+    let last_timestamp = env.block.time.seconds() - 30;
+    let mut config = CONFIG.load(deps.as_ref().storage).unwrap();
+    config.last_timestamp = last_timestamp;
+    config.last_height = env.block.height - 50;
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // Should be get 10 uusd fee => (20 - (50 / 10) * 2), only the height clock moves
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp,
+        last_height: Some(env.block.height),
+        payment: Some(Funds::Native(coin(100, "uusd"))),
+    };
+    assert_eq!(
+        vec![
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: MOCK_RECIPIENT1.into(),
+                amount: coins(10, "uusd"),
+            })),
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: env.contract.address.to_string(),
+                msg: encode_binary(&msg).unwrap(),
+                funds: vec![],
+            }),
+        ],
+        res.msgs
+    );
+    let _res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        msg,
+    )
+    .unwrap();
+
+    // 20 blocks later the fee is down to 16 and next changes 30 blocks after the sale
+    let last_height = env.block.height;
+    env.block.height += 20;
+    env.block.time = env.block.time.plus_seconds(3600);
+    let msg = QueryMsg::CurrentRates {
+        start_after: None,
+        limit: None,
+    };
+    let res: CurrentRatesResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(
+        vec![CurrentRate {
+            index: 0,
+            description: Some("desc2".to_string()),
+            is_additive: true,
+            rate: Rate::Flat(coin(16, "uusd")),
+            next_change: Some(last_height + 30),
+        }],
+        res.rates
+    );
+
+    // No one can move the height clock past the current block
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp,
+        last_height: Some(env.block.height + 1),
+        payment: None,
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let err = execute(deps.as_mut(), env, mock_info(MOCK_OWNER, &[]), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "A sale clock can't be set past the current block".to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_unregistered_marketplace() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        RateInfo {
+            threshold: Some(Thredshold {
+                unit: 2,
+                duration: 60,
+                value: 5,
+                duration_unit: None,
+            }),
+            ..rate_info(
+                Rate::Flat(coin(20, "uusd")),
+                true,
+                Some("listing".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT1)],
+            )
+        },
+        RateInfo {
+            dynamic: Some(DynamicFee {
+                target_interval: 80,
+                max_change: Decimal::percent(10),
+                min: Decimal::percent(1),
+                max: Decimal::percent(10),
+            }),
+            ..rate_info(
+                Rate::from(Decimal::percent(5)),
+                false,
+                Some("royalty".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT2)],
+            )
+        },
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let msg = ExecuteMsg::UpdateCircuitBreaker {
+        circuit_breaker: Some(CircuitBreaker {
+            window: 3600,
+            max_fees: vec![],
+            max_volume_share: None,
+            max_sale_ratio: Some(Decimal::percent(50)),
+            mode: PauseMode::SkipFees,
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    // Without registered hook callers the sale isn't reported, so running the hook's messages
+    // can't fail it
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(1000, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(Funds::Native(coin(950, "uusd")), res.leftover_funds);
+    for sub_msg in res.msgs {
+        if let CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        }) = sub_msg.msg
+        {
+            assert_eq!(env.contract.address.to_string(), contract_addr);
+            let msg: ExecuteMsg = from_binary(&msg).unwrap();
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("marketplace", &[]),
+                msg,
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_query_deducted_funds_sale_condition() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        ),
+        RateInfo {
+            condition: Some(SaleCondition {
+                sale_type: Some(SaleType::Auction),
+                collection: None,
+                token_id: None,
+                buyer: None,
+            }),
+            ..rate_info(
+                Rate::from(Decimal::percent(1)),
+                false,
+                Some("auction".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT1)],
+            )
+        },
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let context = SaleContext {
+        sale_type: SaleType::Auction,
+        collection: Some("collection".to_string()),
+        token_id: Some("1".to_string()),
+        buyer: None,
+    };
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        Some(context),
+    )
+    .unwrap();
+
+    let expected_msgs: Vec<SubMsg> = vec![
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT2.into(),
+            amount: coins(10, "uusd"),
+        })),
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT1.into(),
+            amount: coins(1, "uusd"),
+        })),
+    ];
+    assert_eq!(
+        OnFundsTransferResponse {
+            msgs: expected_msgs,
+            leftover_funds: Funds::Native(coin(89, "uusd")),
+            events: vec![
+                Event::new("royalty")
+                    .add_attribute("description", "desc1")
+                    .add_attribute("deducted", "10uusd")
+                    .add_attribute("payment", "recipient2<10uusd"),
+                Event::new("royalty")
+                    .add_attribute("description", "auction")
+                    .add_attribute("deducted", "1uusd")
+                    .add_attribute("payment", "recipient1<1uusd"),
+            ]
+        },
+        res
+    );
+
+    // Fixed price sales and sales without a context only pay the unconditioned rate
+    let context = SaleContext {
+        sale_type: SaleType::Fixed,
+        collection: None,
+        token_id: None,
+        buyer: None,
+    };
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        Some(context),
+    )
+    .unwrap();
+    assert_eq!(Funds::Native(coin(90, "uusd")), res.leftover_funds);
+
+    let res =
+        query_deducted_funds(deps.as_ref(), env, Funds::Native(coin(100, "uusd")), None).unwrap();
+    assert_eq!(Funds::Native(coin(90, "uusd")), res.leftover_funds);
+}
+
+#[test]
+fn test_query_transfer_fees() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let transfer_rates = vec![rate_info(
+        Rate::Flat(coin(5, "uusd")),
+        true,
+        Some("transfer".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    )];
+    let msg = InstantiateMsg {
+        rates: vec![],
+        transfer_rates: Some(transfer_rates),
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let res = query_transfer_fees(deps.as_ref(), "sender".to_string()).unwrap();
+    assert_eq!(
+        Some(
+            Response::new()
+                .add_submessage(SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: MOCK_RECIPIENT1.into(),
+                    amount: coins(5, "uusd"),
+                })))
+                .add_event(
+                    Event::new("transfer_fee")
+                        .add_attribute("payer", "sender")
+                        .add_attribute("description", "transfer")
+                        .add_attribute("payment", "recipient1<5uusd")
+                )
+        ),
+        res
+    );
+
+    // Transfer rates can only be flat fees
+    let msg = ExecuteMsg::UpdateTransferRates {
+        rates: vec![rate_info(
+            Rate::from(Decimal::percent(1)),
+            true,
+            None,
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+
+    // Options transfer fees don't support are rejected
+    let msg = ExecuteMsg::UpdateTransferRates {
+        rates: vec![RateInfo {
+            shares: Some(vec![Decimal::one()]),
+            ..rate_info(
+                Rate::Flat(coin(10, "uusd")),
+                true,
+                None,
+                vec![Recipient::from_string(MOCK_RECIPIENT1)],
+            )
+        }],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+
+    // Contracts migrated from before transfer rates were kept have none set
+    TRANSFER_RATES.remove(deps.as_mut().storage);
+    let msg = ExecuteMsg::UpdateTransferRates {
+        rates: vec![rate_info(
+            Rate::Flat(coin(10, "uusd")),
+            true,
+            None,
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )],
+    };
+    let _res = execute(deps.as_mut(), env, info, msg).unwrap();
+}
+
+#[test]
+fn test_query_deducted_funds_predicate_skipped() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        RateInfo {
+            predicate: Some(RatePredicate {
+                min_amount: Some(Uint128::new(1000)),
+                max_amount: None,
+                allowed_assets: None,
+                denied_assets: None,
+                asset_kind: None,
+            }),
+            ..rate_info(
+                Rate::from(Decimal::percent(10)),
+                false,
+                Some("desc1".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT2)],
+            )
+        },
+        RateInfo {
+            predicate: Some(RatePredicate {
+                min_amount: None,
+                max_amount: None,
+                allowed_assets: Some(vec!["uusd".to_string()]),
+                denied_assets: None,
+                asset_kind: Some(AssetKind::Native),
+            }),
+            ..rate_info(
+                Rate::Flat(coin(20, "uusd")),
+                true,
+                Some("desc2".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT1)],
+            )
+        },
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+
+    let expected_msgs: Vec<SubMsg> = vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+        to_address: MOCK_RECIPIENT1.into(),
+        amount: coins(20, "uusd"),
+    }))];
+    assert_eq!(
+        OnFundsTransferResponse {
+            msgs: expected_msgs,
+            leftover_funds: Funds::Native(coin(100, "uusd")),
+            events: vec![
+                Event::new("skipped")
+                    .add_attribute("description", "desc1")
+                    .add_attribute("predicate", "min_amount"),
+                Event::new("tax")
+                    .add_attribute("description", "desc2")
+                    .add_attribute("payment", "recipient1<20uusd"),
+            ]
+        },
+        res
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_unaccepted_asset() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    )];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateAcceptedAssets {
+        accepted_assets: Some(AcceptedAssets {
+            native_denoms: vec!["uusd".to_string()],
+            cw20_addresses: vec![],
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(Funds::Native(coin(90, "uusd")), res.leftover_funds);
+
+    let err = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uluna")),
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidFunds {
+            msg: "Payments in native denom uluna are not accepted".to_string()
+        },
+        err
+    );
+
+    let err = query_deducted_funds(
+        deps.as_ref(),
+        env,
+        Funds::Cw20(Cw20Coin {
+            amount: 100u128.into(),
+            address: "address".into(),
+        }),
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidFunds {
+            msg: "Payments in cw20 address are not accepted".to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_flat_asset_mismatch() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![rate_info(
+        Rate::Flat(coin(20, "uusd")),
+        true,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    )];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    // A native flat fee can't be paid out of a cw20 payment
+    let err = query_deducted_funds(
+        deps.as_ref(),
+        env,
+        Funds::Cw20(Cw20Coin {
+            amount: 100u128.into(),
+            address: "address".into(),
+        }),
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(
+        ContractError::InvalidFunds {
+            msg: "Flat fee asset uusd does not match payment asset address".to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_rounding_split() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let mut rate = RateInfo {
+        rounding: Some(Rounding {
+            mode: RoundingMode::Down,
+            dust_recipient: Some(Recipient::from_string("dust")),
+        }),
+        shares: Some(vec![Decimal::percent(30), Decimal::percent(70)]),
+        ..rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![
+                Recipient::from_string(MOCK_RECIPIENT1),
+                Recipient::from_string(MOCK_RECIPIENT2),
+            ],
+        )
+    };
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(50, "uusd")),
+        None,
+    )
+    .unwrap();
+
+    // The fee of 5 is split into 1.5 and 3.5, both rounded down leaving 1 as dust
+    let expected_msgs: Vec<SubMsg> = vec![
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT1.into(),
+            amount: coins(1, "uusd"),
+        })),
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: MOCK_RECIPIENT2.into(),
+            amount: coins(3, "uusd"),
+        })),
+        SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+            to_address: "dust".into(),
+            amount: coins(1, "uusd"),
+        })),
+    ];
+    assert_eq!(
+        OnFundsTransferResponse {
+            msgs: expected_msgs,
+            leftover_funds: Funds::Native(coin(45, "uusd")),
+            events: vec![Event::new("royalty")
+                .add_attribute("description", "desc1")
+                .add_attribute("dust", "1uusd")
+                .add_attribute("deducted", "1uusd")
+                .add_attribute("payment", "recipient1<1uusd")
+                .add_attribute("deducted", "3uusd")
+                .add_attribute("payment", "recipient2<3uusd")
+                .add_attribute("deducted", "1uusd")
+                .add_attribute("payment", "dust<1uusd")]
+        },
+        res
+    );
+
+    // Shares have to add up to the whole fee
+    rate.shares = Some(vec![Decimal::percent(30), Decimal::percent(60)]);
+    let msg = ExecuteMsg::UpdateRates { rates: vec![rate] };
+    let err = execute(deps.as_mut(), env, info, msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+}
+
+#[test]
+fn test_query_deducted_funds_net_base() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        rate_info(
+            Rate::from(Decimal::percent(5)),
+            false,
+            Some("marketplace".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        ),
+        RateInfo {
+            rounding: Some(Rounding {
+                mode: RoundingMode::Down,
+                dust_recipient: None,
+            }),
+            base: Some(RateBase::NetOf {
+                descriptions: vec!["marketplace".to_string()],
+            }),
+            ..rate_info(
+                Rate::from(Decimal::percent(10)),
+                false,
+                Some("royalty".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT2)],
+            )
+        },
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+
+    // The royalty is 10% of the 95 left after the marketplace fee, rounded down
+    assert_eq!(Funds::Native(coin(86, "uusd")), res.leftover_funds);
+    assert_eq!(
+        vec![
+            Event::new("royalty")
+                .add_attribute("description", "marketplace")
+                .add_attribute("deducted", "5uusd")
+                .add_attribute("payment", "recipient1<5uusd"),
+            Event::new("royalty")
+                .add_attribute("description", "royalty")
+                .add_attribute("base", "net_of:marketplace")
+                .add_attribute("base_amount", "95")
+                .add_attribute("deducted", "9uusd")
+                .add_attribute("payment", "recipient2<9uusd"),
+        ],
+        res.events
+    );
+
+    // Flat fees are charged in full whatever the base
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![RateInfo {
+            base: Some(RateBase::NetOf {
+                descriptions: vec![],
+            }),
+            ..rate_info(
+                Rate::Flat(coin(10, "uusd")),
+                false,
+                Some("royalty".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT2)],
+            )
+        }],
+    };
+    let err = execute(deps.as_mut(), env, info, msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+}
+
+#[test]
+fn test_query_deducted_funds_inclusive_tax() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![RateInfo {
+        inclusive: Some(true),
+        ..rate_info(
+            Rate::from(Decimal::percent(10)),
+            true,
+            Some("vat".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
+    }];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res =
+        query_deducted_funds(deps.as_ref(), env, Funds::Native(coin(110, "uusd")), None).unwrap();
+
+    // The tax is carved out of the payment rather than added on top
+    assert_eq!(Funds::Native(coin(100, "uusd")), res.leftover_funds);
+    assert_eq!(
+        vec![Event::new("tax")
+            .add_attribute("description", "vat")
+            .add_attribute("tax_base", "100uusd")
+            .add_attribute("tax", "10uusd")
+            .add_attribute("deducted", "10uusd")
+            .add_attribute("payment", "recipient1<10uusd")],
+        res.events
+    );
+}
+
+#[test]
+fn test_query_deducted_funds_deduction_cap() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        ),
+        rate_info(
+            Rate::Flat(coin(10, "uusd")),
+            false,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        ),
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    // The percentage rates alone already exceed a 5% cap
+    let msg = ExecuteMsg::UpdateDeductionCap {
+        cap: Some(Decimal::percent(5)),
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Deducted rates of 0.1 exceed the deduction cap of 0.05".to_string()
+        },
+        err
+    );
+
+    let msg = ExecuteMsg::UpdateDeductionCap {
+        cap: Some(Decimal::percent(15)),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+
+    // Both fees of 10 are scaled down by 15 / 20
+    assert_eq!(Funds::Native(coin(86, "uusd")), res.leftover_funds);
+    assert_eq!(
+        vec![
+            Event::new("royalty")
+                .add_attribute("description", "desc1")
+                .add_attribute("deducted", "7uusd")
+                .add_attribute("payment", "recipient1<7uusd"),
+            Event::new("royalty")
+                .add_attribute("description", "desc2")
+                .add_attribute("deducted", "7uusd")
+                .add_attribute("payment", "recipient2<7uusd"),
+            Event::new("capped")
+                .add_attribute("cap", "0.15")
+                .add_attribute("deducted", "20uusd")
+                .add_attribute("capped_deducted", "14uusd"),
+        ],
+        res.events
+    );
+
+    // Every recipient of a rate without shares is paid the full fee
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![
+                Recipient::from_string(MOCK_RECIPIENT1),
+                Recipient::from_string(MOCK_RECIPIENT2),
+            ],
+        )],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Deducted rates of 0.2 exceed the deduction cap of 0.15".to_string()
+        },
+        err
+    );
+
+    // Inclusive taxes are neither counted towards the cap nor scaled down
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![
+            rate_info(
+                Rate::from(Decimal::percent(10)),
+                false,
+                Some("desc1".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT1)],
+            ),
+            RateInfo {
+                inclusive: Some(true),
+                ..rate_info(
+                    Rate::from(Decimal::percent(10)),
+                    true,
+                    Some("vat".to_string()),
+                    vec![Recipient::from_string(MOCK_RECIPIENT2)],
+                )
+            },
+        ],
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+    let res =
+        query_deducted_funds(deps.as_ref(), env, Funds::Native(coin(100, "uusd")), None).unwrap();
+    assert!(!res.events.iter().any(|event| event.ty == "capped"));
+}
+
+#[test]
+fn test_query_pricing() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        rate_info(
+            Rate::Flat(coin(20, "uusd")),
+            true,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        ),
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        ),
+        RateInfo {
+            condition: Some(SaleCondition {
+                sale_type: Some(SaleType::Auction),
+                collection: None,
+                token_id: None,
+                buyer: None,
+            }),
+            ..rate_info(
+                Rate::from(Decimal::percent(1)),
+                true,
+                Some("auction".to_string()),
+                vec![Recipient::from_string(MOCK_RECIPIENT1)],
+            )
+        },
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let expected = PricingResponse {
+        price: Funds::Native(coin(100, "uusd")),
+        net: Funds::Native(coin(90, "uusd")),
+        total_buyer_cost: Funds::Native(coin(120, "uusd")),
+    };
+
+    let msg = QueryMsg::NetProceeds {
+        amount: Funds::Native(coin(100, "uusd")),
+        context: None,
+    };
+    let res: PricingResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(expected, res);
+
+    let msg = QueryMsg::TotalBuyerCost {
+        amount: Funds::Native(coin(100, "uusd")),
+        context: None,
+    };
+    let res: PricingResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(expected, res);
+
+    // 99 would only net 89 as the royalty is rounded up
+    let msg = QueryMsg::RequiredPayment {
+        net: Funds::Native(coin(90, "uusd")),
+        context: None,
+    };
+    let res: PricingResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(expected, res);
+
+    // Sale-conditioned rates are priced for the given sale
+    let msg = QueryMsg::TotalBuyerCost {
+        amount: Funds::Native(coin(100, "uusd")),
+        context: Some(SaleContext {
+            sale_type: SaleType::Auction,
+            collection: None,
+            token_id: None,
+            buyer: None,
+        }),
+    };
+    let res: PricingResponse = from_binary(&query(deps.as_ref(), env, msg).unwrap()).unwrap();
+    assert_eq!(Funds::Native(coin(121, "uusd")), res.total_buyer_cost);
+}
+
+#[test]
+fn test_query_rates_paginated_filtered() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rates = vec![
+        rate_info(
+            Rate::Flat(coin(20, "uusd")),
+            true,
+            Some("desc1".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        ),
+        rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc2".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        ),
+        rate_info(
+            Rate::Cw20Flat(Cw20FlatRate {
+                address: "address".to_string(),
+                amount: Uint128::new(5),
+            }),
+            false,
+            Some("desc3".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        ),
+    ];
+    let msg = InstantiateMsg {
+        rates,
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let query_indexes =
+        |start_after: Option<u32>, limit: Option<u32>, filter: Option<RateFilter>| {
+            let msg = QueryMsg::Rates {
+                start_after,
+                limit,
+                filter,
+            };
+            let res: RatesResponse =
+                from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+            res.rates
+                .into_iter()
+                .map(|indexed_rate| indexed_rate.index)
+                .collect::<Vec<u32>>()
+        };
+
+    assert_eq!(vec![0, 1, 2], query_indexes(None, None, None));
+    assert_eq!(vec![0], query_indexes(None, Some(1), None));
+    assert_eq!(vec![1, 2], query_indexes(Some(0), None, None));
+
+    let filter = RateFilter {
+        is_additive: Some(false),
+        asset: None,
+        recipient: None,
+    };
+    assert_eq!(vec![1, 2], query_indexes(None, None, Some(filter)));
+
+    // Percentage rates apply to any asset
+    let filter = RateFilter {
+        is_additive: None,
+        asset: Some("uusd".to_string()),
+        recipient: None,
+    };
+    assert_eq!(vec![0, 1], query_indexes(None, None, Some(filter)));
+
+    let filter = RateFilter {
+        is_additive: None,
+        asset: None,
+        recipient: Some(MOCK_RECIPIENT1.to_string()),
+    };
+    assert_eq!(vec![0, 2], query_indexes(None, None, Some(filter.clone())));
+    assert_eq!(vec![2], query_indexes(Some(0), None, Some(filter)));
+}
+
+#[test]
+fn test_query_rates_for_recipient() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let split_rate = RateInfo {
+        rounding: Some(Rounding {
+            mode: RoundingMode::Down,
+            dust_recipient: Some(Recipient::from_string("dust")),
+        }),
+        shares: Some(vec![Decimal::percent(30), Decimal::percent(70)]),
+        ..rate_info(
+            Rate::from(Decimal::percent(10)),
+            false,
+            Some("desc1".to_string()),
+            vec![
+                Recipient::from_string(MOCK_RECIPIENT1),
+                Recipient::from_string(MOCK_RECIPIENT2),
+            ],
+        )
+    };
+    let flat_rate = rate_info(
+        Rate::Flat(coin(20, "uusd")),
+        true,
+        Some("desc2".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![split_rate.clone(), flat_rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    fn query_rates(deps: Deps, address: &str) -> Vec<RecipientRate> {
+        let msg = QueryMsg::RatesForRecipient {
+            address: address.to_string(),
+        };
+        let res: RatesForRecipientResponse =
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+        res.rates
+    }
+
+    assert_eq!(
+        vec![
+            RecipientRate::new(0, &split_rate, Some(Decimal::percent(30)), false),
+            RecipientRate::new(1, &flat_rate, None, false),
+        ],
+        query_rates(deps.as_ref(), MOCK_RECIPIENT1)
+    );
+    assert_eq!(
+        vec![RecipientRate::new(0, &split_rate, None, true)],
+        query_rates(deps.as_ref(), "dust")
+    );
+
+    // The index is rebuilt when the rates are updated
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![flat_rate.clone()],
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    assert_eq!(
+        vec![RecipientRate::new(0, &flat_rate, None, false)],
+        query_rates(deps.as_ref(), MOCK_RECIPIENT1)
+    );
+    assert!(query_rates(deps.as_ref(), MOCK_RECIPIENT2).is_empty());
+    assert!(query_rates(deps.as_ref(), "dust").is_empty());
+}
+
+#[test]
+fn test_query_rates_at() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let mut later_env = mock_env();
+    later_env.block.height += 10;
+    later_env.block.time = later_env.block.time.plus_seconds(60);
+    let updated_rate = RateInfo {
+        rate: Rate::from(Decimal::percent(20)),
+        ..rate.clone()
+    };
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![updated_rate.clone()],
+    };
+    let _res = execute(deps.as_mut(), later_env.clone(), info, msg).unwrap();
+
+    let first_version = RateVersion {
+        version: 0,
+        height: env.block.height,
+        time: env.block.time.seconds(),
+        rates: vec![rate],
+    };
+    let second_version = RateVersion {
+        version: 1,
+        height: later_env.block.height,
+        time: later_env.block.time.seconds(),
+        rates: vec![updated_rate],
+    };
+
+    let query_rates_at = |time: Option<u64>, height: Option<u64>| {
+        let msg = QueryMsg::RatesAt { time, height };
+        query(deps.as_ref(), env.clone(), msg)
+            .map(|res| from_binary::<RatesAtResponse>(&res).unwrap().version)
+    };
+
+    assert_eq!(
+        None,
+        query_rates_at(Some(env.block.time.seconds() - 1), None).unwrap()
+    );
+    assert_eq!(
+        Some(first_version.clone()),
+        query_rates_at(None, Some(env.block.height + 9)).unwrap()
+    );
+    assert_eq!(
+        Some(second_version.clone()),
+        query_rates_at(None, Some(later_env.block.height)).unwrap()
+    );
+    assert_eq!(
+        Some(first_version.clone()),
+        query_rates_at(Some(later_env.block.time.seconds() - 1), None).unwrap()
+    );
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Exactly one of time or height must be provided".to_string()
+        },
+        query_rates_at(None, None).unwrap_err()
+    );
+
+    let msg = QueryMsg::RateHistory {
+        start_after: None,
+        limit: None,
+    };
+    let res: RateHistoryResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(vec![first_version, second_version.clone()], res.versions);
+
+    let msg = QueryMsg::RateHistory {
+        start_after: Some(0),
+        limit: Some(1),
+    };
+    let res: RateHistoryResponse = from_binary(&query(deps.as_ref(), env, msg).unwrap()).unwrap();
+    assert_eq!(vec![second_version], res.versions);
+}
+
+#[test]
+fn test_migrate_starts_rate_history() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    // A contract from before the rate history and recipient index were kept
+    RATE_VERSIONS.remove(deps.as_mut().storage, 0);
+    RATE_VERSIONS_BY_HEIGHT.remove(deps.as_mut().storage, env.block.height);
+    RATE_VERSIONS_BY_TIME.remove(deps.as_mut().storage, env.block.time.seconds());
+    RECIPIENT_RATES.remove(deps.as_mut().storage, MOCK_RECIPIENT1);
+    set_contract_version(deps.as_mut().storage, "crates.io:andromeda-rates", "0.0.1").unwrap();
+
+    let mut later_env = mock_env();
+    later_env.block.height += 10;
+    later_env.block.time = later_env.block.time.plus_seconds(60);
+    let _res = migrate(deps.as_mut(), later_env.clone(), MigrateMsg {}).unwrap();
+
+    let msg = QueryMsg::RatesAt {
+        time: Some(later_env.block.time.seconds()),
+        height: None,
+    };
+    let res: RatesAtResponse =
+        from_binary(&query(deps.as_ref(), later_env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(
+        Some(RateVersion {
+            version: 0,
+            height: later_env.block.height,
+            time: later_env.block.time.seconds(),
+            rates: vec![rate.clone()],
+        }),
+        res.version
+    );
+
+    let msg = QueryMsg::RatesForRecipient {
+        address: MOCK_RECIPIENT1.to_string(),
+    };
+    let res: RatesForRecipientResponse =
+        from_binary(&query(deps.as_ref(), later_env, msg).unwrap()).unwrap();
+    assert_eq!(vec![RecipientRate::new(0, &rate, None, false)], res.rates);
+}
+
+#[test]
+fn test_query_audit_log() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateDeductionCap {
+        cap: Some(Decimal::percent(50)),
+    };
+    let res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    assert_eq!(
+        vec![Event::new("audit")
+            .add_attribute("id", "0")
+            .add_attribute("actor", MOCK_OWNER)
+            .add_attribute("time", env.block.time.seconds().to_string())
+            .add_attribute("action", "update_deduction_cap")
+            .add_attribute("cap.new", "\"0.5\"")],
+        res.events
+    );
+
+    let second_rate = RateInfo {
+        description: Some("desc2".to_string()),
+        ..rate.clone()
+    };
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![rate.clone(), second_rate.clone()],
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    // Moving the sale clock is audited even when it moves forward as a sale would
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: env.block.time.seconds(),
+        last_height: None,
+        payment: None,
+    };
+    let _res = execute(deps.as_mut(), env.clone(), mock_info(MOCK_OWNER, &[]), msg).unwrap();
+
+    let msg = QueryMsg::AuditLog {
+        start_after: Some(0),
+        limit: None,
+    };
+    let res: AuditLogResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(
+        vec![
+            AuditEntry {
+                id: 1,
+                actor: MOCK_OWNER.to_string(),
+                origin: None,
+                time: env.block.time.seconds(),
+                action: "update_rates".to_string(),
+                changes: vec![AuditChange {
+                    field: "rates[1]".to_string(),
+                    old: None,
+                    new: Some(String::from_utf8(to_vec(&second_rate).unwrap()).unwrap()),
+                }],
+            },
+            AuditEntry {
+                id: 2,
+                actor: MOCK_OWNER.to_string(),
+                origin: None,
+                time: env.block.time.seconds(),
+                action: "update_sale_timestamp".to_string(),
+                changes: vec![AuditChange {
+                    field: "last_timestamp".to_string(),
+                    old: Some("0".to_string()),
+                    new: Some(env.block.time.seconds().to_string()),
+                }],
+            }
+        ],
+        res.entries
+    );
+}
+
+#[test]
+fn test_rate_change_proposals() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    // The owner makes itself the manager of the rate before installing the approvers
+    let msg = ExecuteMsg::UpdateRoles {
+        address: MOCK_OWNER.to_string(),
+        roles: Some(Roles {
+            operator: false,
+            managed_rates: vec!["desc1".to_string()],
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateApprovers {
+        approver_set: Some(ApproverSet {
+            approvers: vec![
+                "approver1".to_string(),
+                "approver2".to_string(),
+                "approver3".to_string(),
+            ],
+            threshold: 2,
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    // Installing the approvers revokes the managed rates
+    let msg = QueryMsg::Roles {
+        address: MOCK_OWNER.to_string(),
+    };
+    let res: RolesResponse = from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(None, res.roles);
+    let msg = ExecuteMsg::UpdateRate {
+        description: "desc1".to_string(),
+        rate: RateInfo {
+            rate: Rate::from(Decimal::percent(20)),
+            ..rate.clone()
+        },
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+
+    // The owner can no longer change the rates directly
+    let updated_rate = RateInfo {
+        rate: Rate::from(Decimal::percent(20)),
+        ..rate.clone()
+    };
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![updated_rate.clone()],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Rate changes have to be proposed to the approvers".to_string()
+        },
+        err
+    );
+    let msg = ExecuteMsg::UpdateTransferRates { rates: vec![] };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Transfer rate changes have to be proposed to the approvers".to_string()
+        },
+        err
+    );
+    let msg = ExecuteMsg::UpdateDeductionCap {
+        cap: Some(Decimal::percent(50)),
+    };
+    let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Deduction cap changes have to be proposed to the approvers".to_string()
+        },
+        err
+    );
+
+    let msg = ExecuteMsg::Propose {
+        action: ProposalAction::UpdateRates {
+            rates: vec![updated_rate.clone()],
+        },
+        expiration: env.block.time.seconds() + 100,
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(deps.as_mut(), env.clone(), mock_info("approver1", &[]), msg).unwrap();
+
+    let proposal: Proposal = from_binary(
+        &query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Proposal { proposal_id: 0 },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(vec!["approver1".to_string()], proposal.approvals);
+    assert!(!proposal.executed);
+
+    // The proposal can't be approved once it expires
+    let mut expired_env = env.clone();
+    expired_env.block.time = expired_env.block.time.plus_seconds(100);
+    let msg = ExecuteMsg::Approve { proposal_id: 0 };
+    let err = execute(
+        deps.as_mut(),
+        expired_env,
+        mock_info("approver2", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Proposal 0 has expired".to_string()
+        },
+        err
+    );
+
+    let res = execute(deps.as_mut(), env.clone(), mock_info("approver2", &[]), msg).unwrap();
+    assert_eq!(
+        vec![
+            attr("action", "approve"),
+            attr("proposal_id", "0"),
+            attr("executed", "true")
+        ],
+        res.attributes
+    );
+
+    let payments: PaymentsResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Payments {}).unwrap()).unwrap();
+    assert_eq!(vec![updated_rate], payments.payments);
+
+    // The deduction cap is changed through the approvers as well
+    let msg = ExecuteMsg::Propose {
+        action: ProposalAction::UpdateDeductionCap {
+            cap: Some(Decimal::percent(50)),
+        },
+        expiration: env.block.time.seconds() + 100,
+    };
+    let _res = execute(deps.as_mut(), env.clone(), mock_info("approver1", &[]), msg).unwrap();
+    let msg = ExecuteMsg::Approve { proposal_id: 1 };
+    let _res = execute(deps.as_mut(), env.clone(), mock_info("approver3", &[]), msg).unwrap();
+
+    let res: DeductionCapResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::DeductionCap {}).unwrap())
+            .unwrap();
+    assert_eq!(Some(Decimal::percent(50)), res.cap);
+
+    let res: ProposalsResponse = from_binary(
+        &query(
+            deps.as_ref(),
+            env,
+            QueryMsg::Proposals {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(2, res.proposals.len());
+    assert!(res.proposals.iter().all(|proposal| proposal.executed));
+}
+
+#[test]
+fn test_rate_manager_and_operator_roles() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let royalty = rate_info(
+        Rate::from(Decimal::percent(5)),
+        false,
+        Some("royalty".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let marketplace_fee = RateInfo {
+        description: Some("marketplace_fee".to_string()),
+        recipients: vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        ..royalty.clone()
+    };
+    let msg = InstantiateMsg {
+        rates: vec![royalty.clone(), marketplace_fee.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let creator_roles = Roles {
+        operator: false,
+        managed_rates: vec!["royalty".to_string()],
+    };
+    let msg = ExecuteMsg::UpdateRoles {
+        address: "creator".to_string(),
+        roles: Some(creator_roles.clone()),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let msg = ExecuteMsg::UpdateRoles {
+        address: "operator".to_string(),
+        roles: Some(Roles {
+            operator: true,
+            managed_rates: vec![],
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res: RolesResponse = from_binary(
+        &query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Roles {
+                address: "creator".to_string(),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(Some(creator_roles), res.roles);
+
+    // The creator manages their royalty but not the marketplace fee
+    let updated_royalty = RateInfo {
+        rate: Rate::from(Decimal::percent(8)),
+        ..royalty
+    };
+    let msg = ExecuteMsg::UpdateRate {
+        description: "royalty".to_string(),
+        rate: updated_royalty.clone(),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    let msg = ExecuteMsg::UpdateRate {
+        description: "marketplace_fee".to_string(),
+        rate: RateInfo {
+            rate: Rate::from(Decimal::zero()),
+            ..marketplace_fee.clone()
+        },
+    };
+    let err = execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+
+    let payments: PaymentsResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Payments {}).unwrap()).unwrap();
+    assert_eq!(vec![updated_royalty, marketplace_fee], payments.payments);
+
+    // Operators can reset the sale clock but can't change rates
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: 0,
+        last_height: None,
+        payment: None,
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(deps.as_mut(), env.clone(), mock_info("operator", &[]), msg).unwrap();
+    let msg = ExecuteMsg::UpdateRates { rates: vec![] };
+    let err = execute(deps.as_mut(), env.clone(), mock_info("operator", &[]), msg).unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+
+    let res: RoleHoldersResponse = from_binary(
+        &query(
+            deps.as_ref(),
+            env,
+            QueryMsg::RoleHolders {
+                start_after: Some("creator".to_string()),
+                limit: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        vec!["operator".to_string()],
+        res.holders
+            .into_iter()
+            .map(|holder| holder.address)
+            .collect::<Vec<String>>()
+    );
+}
+
+#[test]
+fn test_rotate_recipient() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("royalty".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateRotationVetoPeriod { period: Some(100) };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let rotate_msg = ExecuteMsg::RotateRecipient {
+        description: "royalty".to_string(),
+        recipient: Recipient::from_string("new_recipient"),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        rotate_msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+
+    // The owner vetoes the first rotation
+    let _res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(MOCK_RECIPIENT1, &[]),
+        rotate_msg.clone(),
+    )
+    .unwrap();
+    let msg = ExecuteMsg::VetoRotation { rotation_id: 0 };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(MOCK_RECIPIENT1, &[]),
+        rotate_msg,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![Event::new("rotate_recipient")
+            .add_attribute("rate", "royalty")
+            .add_attribute("old_recipient", MOCK_RECIPIENT1)
+            .add_attribute("new_recipient", "new_recipient")
+            .add_attribute("status", "pending")
+            .add_attribute("rotation_id", "1")
+            .add_attribute(
+                "executable_at",
+                (env.block.time.seconds() + 100).to_string()
+            )],
+        res.events
+    );
+
+    let msg = ExecuteMsg::ApplyRotation { rotation_id: 1 };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: format!(
+                "Rotation 1 can't be applied before {}",
+                env.block.time.seconds() + 100
+            )
+        },
+        err
+    );
+
+    let mut later_env = env.clone();
+    later_env.block.time = later_env.block.time.plus_seconds(100);
+    let _res = execute(deps.as_mut(), later_env, mock_info("anyone", &[]), msg).unwrap();
+
+    let payments: PaymentsResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Payments {}).unwrap()).unwrap();
+    assert_eq!(
+        vec![Recipient::from_string("new_recipient")],
+        payments.payments[0].recipients
+    );
+
+    let res: PendingRotationsResponse = from_binary(
+        &query(
+            deps.as_ref(),
+            env,
+            QueryMsg::PendingRotations {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(res.rotations.is_empty());
+}
+
+#[test]
+fn test_rate_commitment() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let royalty = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("royalty".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![royalty.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let expiration = env.block.time.seconds() + 100;
+    let commitment = RateCommitment {
+        description: "royalty".to_string(),
+        floor: Some(Rate::from(Decimal::percent(5))),
+        expiration: Some(expiration),
+        immutable: false,
+    };
+    let msg = ExecuteMsg::CommitRate {
+        commitment: commitment.clone(),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap();
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Rate royalty is already committed".to_string()
+        },
+        err
+    );
+
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![RateInfo {
+            rate: Rate::from(Decimal::percent(4)),
+            ..royalty.clone()
+        }],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: format!(
+                "Rate royalty is committed until {expiration}, the rate can't drop below its floor"
+            )
+        },
+        err
+    );
+
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        info.clone(),
+        ExecuteMsg::UpdateRates { rates: vec![] },
+    )
+    .unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: format!("Rate royalty is committed until {expiration}, the rate can't be removed")
+        },
+        err
+    );
+
+    // Splitting the fee with a new recipient lowers what the committed recipient is paid
+    let split_msg = ExecuteMsg::UpdateRates {
+        rates: vec![RateInfo {
+            rate: Rate::from(Decimal::percent(20)),
+            recipients: vec![
+                Recipient::from_string(MOCK_RECIPIENT1),
+                Recipient::from_string(MOCK_RECIPIENT2),
+            ],
+            shares: Some(vec![Decimal::percent(50), Decimal::percent(50)]),
+            ..royalty.clone()
+        }],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), split_msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: format!(
+                "Rate royalty is committed until {expiration}, only its rate and recipients can be changed"
+            )
+        },
+        err
+    );
+
+    let res: CommitmentsResponse = from_binary(
+        &query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Commitments {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(vec![commitment], res.commitments);
+
+    // The rate can be lowered once the commitment expires
+    let mut later_env = env;
+    later_env.block.time = later_env.block.time.plus_seconds(100);
+    let _res = execute(deps.as_mut(), later_env, info, msg).unwrap();
+}
+
+#[test]
+fn test_rate_commitment_lowered_fees() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let royalty = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("royalty".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    // Decays from 100 down to 10 over the minutes since the last sale
+    let listing = RateInfo {
+        threshold: Some(Thredshold {
+            unit: 10,
+            duration: 60,
+            value: 10,
+            duration_unit: None,
+        }),
+        ..rate_info(
+            Rate::Flat(coin(100, "uusd")),
+            true,
+            Some("listing".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT2)],
+        )
+    };
+    let msg = InstantiateMsg {
+        rates: vec![royalty, listing],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    // The decayed fee is below the floor
+    let commitment = RateCommitment {
+        description: "listing".to_string(),
+        floor: Some(Rate::Flat(coin(50, "uusd"))),
+        expiration: None,
+        immutable: false,
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        info.clone(),
+        ExecuteMsg::CommitRate { commitment },
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+
+    let commitment = RateCommitment {
+        description: "listing".to_string(),
+        floor: Some(Rate::Flat(coin(10, "uusd"))),
+        expiration: None,
+        immutable: false,
+    };
+    let msg = ExecuteMsg::CommitRate { commitment };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: env.block.time.seconds(),
+        last_height: None,
+        payment: None,
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    // Setting the clock back decays the fee
+    let msg = ExecuteMsg::UpdateSaleTimestamp {
+        last_timestamp: env.block.time.seconds() - 600,
+        last_height: None,
+        payment: None,
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Rate listing is committed permanently, its sale clock can't be set back"
+                .to_string()
+        },
+        err
+    );
+
+    let msg = ExecuteMsg::UpdateClockResetPolicy {
+        policy: Some(ClockResetPolicy::Gradual { max_recovery: 0 }),
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg:
+                "Rate listing is committed permanently, the clock reset policy can only be removed"
+                    .to_string()
+        },
+        err
+    );
+    let msg = ExecuteMsg::UpdateClockResetPolicy { policy: None };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    // The cap only scales down the deducted rates
+    let msg = ExecuteMsg::UpdateDeductionCap {
+        cap: Some(Decimal::percent(50)),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let commitment = RateCommitment {
+        description: "royalty".to_string(),
+        floor: None,
+        expiration: None,
+        immutable: false,
+    };
+    let msg = ExecuteMsg::CommitRate { commitment };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let msg = ExecuteMsg::UpdateDeductionCap {
+        cap: Some(Decimal::percent(40)),
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Rate royalty is committed permanently, the deduction cap can't be lowered"
+                .to_string()
+        },
+        err
+    );
+    // Raising or removing the cap is allowed
+    let msg = ExecuteMsg::UpdateDeductionCap { cap: None };
+    let _res = execute(deps.as_mut(), env, info, msg).unwrap();
+}
+
+#[test]
+fn test_pause() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = rate_info(
+        Rate::from(Decimal::percent(10)),
+        false,
+        Some("desc1".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let hook_msg = QueryMsg::AndrHook(AndromedaHook::OnFundsTransfer {
+        sender: "seller".to_string(),
+        payload: Binary::default(),
+        amount: Funds::Native(coin(100, "uusd")),
+    });
+
+    let until = env.block.time.seconds() + 100;
+    let msg = ExecuteMsg::Pause {
+        mode: PauseMode::SkipFees,
+        until: Some(until),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let res: OnFundsTransferResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), hook_msg.clone()).unwrap()).unwrap();
+    assert_eq!(
+        OnFundsTransferResponse {
+            msgs: vec![],
+            leftover_funds: Funds::Native(coin(100, "uusd")),
+            events: vec![],
+        },
+        res
+    );
+    // The pricing queries match what the hook charges
+    let pricing_msg = QueryMsg::NetProceeds {
+        amount: Funds::Native(coin(100, "uusd")),
+        context: None,
+    };
+    let res: PricingResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), pricing_msg.clone()).unwrap()).unwrap();
+    assert_eq!(
+        PricingResponse {
+            price: Funds::Native(coin(100, "uusd")),
+            net: Funds::Native(coin(100, "uusd")),
+            total_buyer_cost: Funds::Native(coin(100, "uusd")),
+        },
+        res
+    );
+    let msg = QueryMsg::RequiredPayment {
+        net: Funds::Native(coin(90, "uusd")),
+        context: None,
+    };
+    let res: PricingResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+    assert_eq!(Funds::Native(coin(90, "uusd")), res.price);
+    let res: PauseStatusResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::PauseStatus {}).unwrap()).unwrap();
+    assert_eq!(
+        Some(Pause {
+            mode: PauseMode::SkipFees,
+            until: Some(until),
+        }),
+        res.pause
+    );
+
+    // Fees are collected again once the pause ends
+    let mut later_env = env.clone();
+    later_env.block.time = later_env.block.time.plus_seconds(100);
+    let res: OnFundsTransferResponse =
+        from_binary(&query(deps.as_ref(), later_env.clone(), hook_msg.clone()).unwrap()).unwrap();
+    assert_eq!(Funds::Native(coin(90, "uusd")), res.leftover_funds);
+    let res: PauseStatusResponse =
+        from_binary(&query(deps.as_ref(), later_env, QueryMsg::PauseStatus {}).unwrap()).unwrap();
+    assert_eq!(None, res.pause);
+
+    let msg = ExecuteMsg::Pause {
+        mode: PauseMode::HaltSales,
+        until: None,
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let err = query(deps.as_ref(), env.clone(), hook_msg.clone()).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Sales are halted while fee collection is paused".to_string()
+        },
+        err
+    );
+    let err = query(deps.as_ref(), env.clone(), pricing_msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Sales are halted while fee collection is paused".to_string()
+        },
+        err
+    );
+    // Transfers still go through, without fees
+    let transfer_msg = QueryMsg::AndrHook(AndromedaHook::OnTokenTransfer {
+        token_id: "token".to_string(),
+        sender: "seller".to_string(),
+        recipient: "buyer".to_string(),
+    });
+    let res: Option<Response> =
+        from_binary(&query(deps.as_ref(), env.clone(), transfer_msg).unwrap()).unwrap();
+    assert_eq!(None, res);
+
+    let _res = execute(deps.as_mut(), env.clone(), info, ExecuteMsg::Unpause {}).unwrap();
+    assert!(query(deps.as_ref(), env, hook_msg).is_ok());
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    // A misconfigured royalty taking most of the payment
+    let rate = rate_info(
+        Rate::from(Decimal::percent(60)),
+        false,
+        Some("royalty".to_string()),
+        vec![Recipient::from_string(MOCK_RECIPIENT1)],
+    );
+    let msg = InstantiateMsg {
+        rates: vec![rate],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateCircuitBreaker {
+        circuit_breaker: Some(CircuitBreaker {
+            window: 3600,
+            max_fees: vec![],
+            max_volume_share: None,
+            max_sale_ratio: Some(Decimal::percent(50)),
+            mode: PauseMode::SkipFees,
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    let record_msg = ExecuteMsg::RecordFees {
+        payment: Funds::Native(coin(100, "uusd")),
+        fees: Uint128::new(60),
+    };
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    // The trip isn't reported until the hook callers are registered
+    assert_eq!(
+        OnFundsTransferResponse {
+            msgs: vec![],
+            leftover_funds: Funds::Native(coin(100, "uusd")),
+            events: vec![Event::new("circuit_breaker")
+                .add_attribute("tripped", "sale fees exceed 0.5 of the payment")],
+        },
+        res
+    );
+
+    // Only registered hook callers can report sales
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        record_msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let msg = ExecuteMsg::UpdateHookCallers {
+        callers: vec!["marketplace".to_string()],
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+    let res: HookCallersResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::HookCallers {}).unwrap()).unwrap();
+    assert_eq!(vec!["marketplace".to_string()], res.callers);
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: encode_binary(&record_msg).unwrap(),
+            funds: vec![],
+        })],
+        res.msgs
+    );
+
+    // Recording the sale persists the trip
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        record_msg.clone(),
+    )
+    .unwrap();
+    assert_eq!(
+        vec![Event::new("circuit_breaker")
+            .add_attribute("tripped", "sale fees exceed 0.5 of the payment")
+            .add_attribute("time", env.block.time.seconds().to_string())],
+        res.events
+    );
+
+    let hook_msg = QueryMsg::AndrHook(AndromedaHook::OnFundsTransfer {
+        sender: "seller".to_string(),
+        payload: Binary::default(),
+        amount: Funds::Native(coin(100, "uusd")),
+    });
+    let res: OnFundsTransferResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), hook_msg.clone()).unwrap()).unwrap();
+    assert!(res.msgs.is_empty());
+
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("anyone", &[]),
+        ExecuteMsg::ResetCircuitBreaker {},
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let _res = execute(
+        deps.as_mut(),
+        env.clone(),
+        info.clone(),
+        ExecuteMsg::ResetCircuitBreaker {},
+    )
+    .unwrap();
+
+    let res: CircuitBreakerResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::CircuitBreaker {}).unwrap())
+            .unwrap();
+    assert_eq!(BreakerState::default(), res.state);
+
+    // Halting sales fails the sales after the trip, the sale tripping it goes through without
+    // fees so that it can record the trip
+    let msg = ExecuteMsg::UpdateCircuitBreaker {
+        circuit_breaker: Some(CircuitBreaker {
+            window: 3600,
+            max_fees: vec![],
+            max_volume_share: None,
+            max_sale_ratio: Some(Decimal::percent(50)),
+            mode: PauseMode::HaltSales,
+        }),
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(100, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert_eq!(Funds::Native(coin(100, "uusd")), res.leftover_funds);
+    assert_eq!(
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: encode_binary(&record_msg).unwrap(),
+            funds: vec![],
+        })],
+        res.msgs
+    );
+    let _res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        record_msg,
+    )
+    .unwrap();
+
+    let err = query(deps.as_ref(), env, hook_msg).unwrap_err();
+    assert_eq!(
+        ContractError::CustomError {
+            msg: "Sales are halted while fee collection is paused".to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_dynamic_rate() {
+    let mut deps = mock_dependencies_custom(&[]);
+    let mut env = mock_env();
+    let info = mock_info(MOCK_OWNER, &[]);
+    let rate = RateInfo {
+        dynamic: Some(DynamicFee {
+            target_interval: 80,
+            max_change: Decimal::percent(10),
+            min: Decimal::percent(1),
+            max: Decimal::percent(10),
+        }),
+        ..rate_info(
+            Rate::from(Decimal::percent(5)),
+            false,
+            Some("royalty".to_string()),
+            vec![Recipient::from_string(MOCK_RECIPIENT1)],
+        )
+    };
+    let msg = InstantiateMsg {
+        rates: vec![rate.clone()],
+        transfer_rates: None,
+        kernel_address: MOCK_KERNEL_CONTRACT.to_string(),
+        owner: None,
+    };
+    let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+    // Only percentage rates can be dynamic
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![RateInfo {
+            rate: Rate::Flat(coin(10, "uusd")),
+            ..rate.clone()
+        }],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+
+    // A rate of zero could never be raised again
+    let msg = ExecuteMsg::UpdateRates {
+        rates: vec![RateInfo {
+            dynamic: Some(DynamicFee {
+                target_interval: 80,
+                max_change: Decimal::percent(10),
+                min: Decimal::zero(),
+                max: Decimal::percent(10),
+            }),
+            ..rate
+        }],
+    };
+    let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+    assert_eq!(ContractError::InvalidRate {}, err);
+
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(1000, "uusd")),
+        None,
+    )
+    .unwrap();
+    let record_msg = SubMsg::new(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: encode_binary(&ExecuteMsg::RecordSale {}).unwrap(),
+        funds: vec![],
+    });
+    // Sales aren't reported until the hook callers are registered
+    assert!(!res.msgs.contains(&record_msg));
+    assert_eq!(Funds::Native(coin(950, "uusd")), res.leftover_funds);
+
+    // Only registered hook callers can report sales
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        ExecuteMsg::RecordSale {},
+    )
+    .unwrap_err();
+    assert_eq!(ContractError::Unauthorized {}, err);
+    let msg = ExecuteMsg::UpdateHookCallers {
+        callers: vec!["marketplace".to_string()],
+    };
+    let _res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+    let res = query_deducted_funds(
+        deps.as_ref(),
+        env.clone(),
+        Funds::Native(coin(1000, "uusd")),
+        None,
+    )
+    .unwrap();
+    assert!(res.msgs.contains(&record_msg));
+
+    // A sale arriving faster than targeted raises the rate
+    let _res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        ExecuteMsg::RecordSale {},
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(16);
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("marketplace", &[]),
+        ExecuteMsg::RecordSale {},
+    )
+    .unwrap();
+    assert_eq!(
+        vec![Event::new("dynamic_rate")
+            .add_attribute("description", "royalty")
+            .add_attribute("percent", "0.0505")
+            .add_attribute("average_interval", "72")],
+        res.events
+    );
+
+    let query_msg = QueryMsg::DynamicRate {
+        description: "royalty".to_string(),
+    };
+    let res: DynamicFeeState =
+        from_binary(&query(deps.as_ref(), env.clone(), query_msg).unwrap()).unwrap();
+    assert_eq!(
+        DynamicFeeState {
+            percent: Decimal::from_ratio(505u128, 10000u128),
+            average_interval: 72,
+            last_sale: env.block.time.seconds(),
+        },
+        res
+    );
+
+    let res =
+        query_deducted_funds(deps.as_ref(), env, Funds::Native(coin(10000, "uusd")), None).unwrap();
+    assert_eq!(Funds::Native(coin(9495, "uusd")), res.leftover_funds);
+}